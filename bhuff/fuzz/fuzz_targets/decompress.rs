@@ -0,0 +1,22 @@
+/* Arbitrary input must give an error, never a panic or excessive allocation:
+ *
+ *   cargo +nightly fuzz run decompress -- -rss_limit_mb=512
+ *
+ * Compressed files make a good seed corpus, e.g. in fuzz/corpus/decompress;
+ * .bz2 files go to the bzip2 decoder, as with bhuff -d.
+ */
+
+#![no_main]
+
+use std::io::Read;
+use libfuzzer_sys::fuzz_target;
+use bhuff::{BhuffDecoder,Bz2Decoder};
+
+fuzz_target!(|data: &[u8]| {
+    let mut out = Vec::new();
+    if data.starts_with(b"BZh") {
+        let _ = Bz2Decoder::new(data).memory_limit(64 << 20).read_to_end(&mut out);
+        return;
+    }
+    let _ = BhuffDecoder::new(data).threads(2).memory_limit(64 << 20).read_to_end(&mut out);
+});
@@ -23,14 +23,15 @@ impl Bits for RealBits {
 
     fn append(&self, b: bool) -> Self {
         let RealBits(i, siz) = self;
+        assert!((*siz as u32) < usize::BITS, "bit string too long");
         RealBits(i<<1 | (b as usize), siz+1)
     }
 
 }
 
 impl RealBits {
-    pub fn from_u8(n: u8) -> Self {
-        RealBits(n as usize, 8)
+    pub fn with_len(n: usize, len: u8) -> Self {
+        RealBits(n, len)
     }
 }
 
@@ -47,3 +48,61 @@ impl Iterator for RealBits {
         }
     }
 }
+
+/* Reads a byte slice most significant bit first, through a 64-bit buffer;
+ * reading past the end yields zero bits, which is detected by `overrun`.
+ */
+pub struct BitReader<'a> {
+    data: &'a [u8],
+    buf: u64,
+    count: u32,
+    padding: u32,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        BitReader { data, buf: 0, count: 0, padding: 0 }
+    }
+
+    fn refill(&mut self) {
+        while self.count <= 56 {
+            let byte = match self.data.split_first() {
+                Some((&byte, rest)) => { self.data = rest; byte },
+                None                => { self.padding += 8; 0 },
+            };
+            self.buf |= (byte as u64) << (56 - self.count);
+            self.count += 8;
+        }
+    }
+
+    // n must be between 1 and 32
+    pub fn peek(&mut self, n: u32) -> u32 {
+        if self.count < n {
+            self.refill();
+        }
+        (self.buf >> (64 - n)) as u32
+    }
+
+    pub fn consume(&mut self, n: u32) {
+        self.buf <<= n;
+        self.count -= n;
+    }
+
+    // the number of bits that have not been read yet
+    pub fn remaining(&self) -> usize {
+        (self.data.len() * 8 + self.count as usize).saturating_sub(self.padding as usize)
+    }
+
+    pub fn overrun(&self) -> bool {
+        self.count < self.padding
+    }
+}
+
+impl Iterator for BitReader<'_> {
+    type Item = bool;
+    fn next(&mut self) -> Option<bool> {
+        let bit = self.peek(1) != 0;
+        self.consume(1);
+        (!self.overrun()).then_some(bit)
+    }
+}
@@ -0,0 +1,182 @@
+/* Encoding and decoding of single blocks; see header.rs for their layout */
+
+use std::io::Read;
+use std::collections::HashMap;
+use std::thread;
+
+use crate::huffman::{BitString,huffman_encode,read_code_lengths,canonical_codes};
+use crate::tables;
+use crate::range;
+use crate::dynamic;
+use crate::bitstring::BitReader;
+use crate::lookup::LookupTable;
+use crate::header::{self,Header,put_varint,get_varint,get_u32};
+use crate::error::{BhuffError,Result};
+use crate::crc;
+use crate::pipeline::Output;
+
+/* block sizes are chosen as in bzip2, from -1 (100k) to -9 (900k) */
+pub const BLOCK_UNIT: usize = 100_000;
+pub const MAX_BLOCK_SIZE: usize = 9 * BLOCK_UNIT;
+
+/* blocks are independent, so a batch of them can be processed concurrently */
+pub fn in_parallel<T: Send, R: Send>(jobs: Vec<T>, work: impl Fn(T) -> R + Sync) -> Vec<R> {
+    let work = &work;
+    thread::scope(|scope| {
+        let handles: Vec<_> = jobs.into_iter().map(|job| scope.spawn(move || work(job))).collect();
+        handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+    })
+}
+
+// the static codes are used for the methods with a static tree
+pub fn encode_block(header: &Header, static_codes: Option<&HashMap<u16,BitString>>, data: Vec<u8>) -> (Vec<u8>, u32) {
+    let inp_len = data.len();
+    let block_crc = crc::crc32(&data);
+    let (params, block) = header.pipeline.transform(data);
+    let payload = match header.method {
+        header::METHOD_TABLES => tables::encode(&block),
+        header::METHOD_RANGE0 => range::encode(0, &block),
+        header::METHOD_RANGE1 => range::encode(1, &block),
+        _ => huffman_encode(static_codes, &block),
+    };
+
+    let mut out = Vec::new();
+    put_varint(&mut out, inp_len);
+    out.extend(block_crc.to_be_bytes());
+    for param in params {
+        put_varint(&mut out, param);
+    }
+    put_varint(&mut out, block.len());
+    put_varint(&mut out, payload.len());
+    out.extend(payload);
+    (out, block_crc)
+}
+
+// the bytes are coded as they are, with a tree that carries over from the previous block
+pub fn encode_dynamic_block(tree: &mut dynamic::Tree, data: &[u8]) -> (Vec<u8>, u32) {
+    let block_crc = crc::crc32(data);
+    let payload = tree.encode(data);
+
+    let mut out = Vec::new();
+    put_varint(&mut out, data.len());
+    out.extend(block_crc.to_be_bytes());
+    put_varint(&mut out, payload.len());
+    out.extend(payload);
+    (out, block_crc)
+}
+
+pub fn read_input_block(input: &mut impl Read, block_size: usize) -> Result<Vec<u8>> {
+    let mut block = Vec::new();
+    input.take(block_size as u64).read_to_end(&mut block)?;
+    Ok(block)
+}
+
+/* a block as stored in the stream, before any decoding has been done */
+pub struct Block {
+    pub index: usize,
+    pub length: usize,
+    pub crc: u32,
+    pub params: Vec<usize>,
+    pub symbols: usize,
+    pub payload: Vec<u8>,
+}
+
+// returns None for the block that terminates a stream
+pub fn read_block(input: &mut impl Read, index: usize, header: &Header) -> Result<Option<Block>> {
+    let length = get_varint(input)?;
+    if length == 0 {
+        return Ok(None);
+    }
+    if length > header.block_size {
+        return Err(BhuffError::Corrupt(Some(index), "block too large"));
+    }
+    let crc = get_u32(input)?;
+    if header.method == header::METHOD_DYNAMIC {
+        return read_payload(input, index, dynamic::max_payload(length))
+            .map(|payload| Some(Block { index, length, crc, params: Vec::new(), symbols: length, payload }));
+    }
+    // the stages check their parameters when they are undone
+    let params = (0..header.pipeline.params()).map(|_| get_varint(input)).collect::<Result<Vec<usize>>>()?;
+    // zero-run coding never makes the data longer, and every symbol has a maximum cost
+    let symbols = get_varint(input)?;
+    if symbols == 0 || symbols > header.pipeline.max_len(length) {
+        return Err(BhuffError::Corrupt(Some(index), "too many symbols"));
+    }
+    let max_payload = match header.method {
+        header::METHOD_RANGE0 | header::METHOD_RANGE1 => range::max_payload(symbols),
+        _ => tables::max_payload(symbols),
+    };
+    let payload = read_payload(input, index, max_payload)?;
+    Ok(Some(Block { index, length, crc, params, symbols, payload }))
+}
+
+pub fn read_payload(input: &mut impl Read, index: usize, max_payload: usize) -> Result<Vec<u8>> {
+    let size = get_varint(input)?;
+    if size > max_payload {
+        return Err(BhuffError::Corrupt(Some(index), "payload too large"));
+    }
+    let mut payload = Vec::new();
+    input.take(size as u64).read_to_end(&mut payload)?;
+    if payload.len() != size {
+        return Err(BhuffError::Truncated);
+    }
+    Ok(payload)
+}
+
+/* a decoded block, whose data is reconstructed while it is being read; the
+ * length and CRC are checked when it runs out */
+pub struct Decoded {
+    index: usize,
+    length: usize,
+    crc: u32,
+    data: Output,
+    produced: usize,
+    running_crc: u32,
+}
+
+impl Decoded {
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = buf.iter_mut().zip(&mut self.data).map(|(slot, byte)| *slot = byte).count();
+        self.running_crc = crc::update(self.running_crc, &buf[..n]);
+        self.produced += n;
+        if n > 0 || buf.is_empty() {
+            Ok(n)
+        } else if self.produced != self.length {
+            Err(BhuffError::Corrupt(Some(self.index), "wrong length"))
+        } else if self.running_crc != self.crc {
+            Err(BhuffError::Corrupt(Some(self.index), "CRC mismatch"))
+        } else {
+            Ok(0)
+        }
+    }
+}
+
+// blocks have their own tables, unless the stream uses a static one
+pub fn decode_block(header: &Header, static_table: Option<&LookupTable>, block: Block) -> Result<Decoded> {
+    let corrupt = |what| BhuffError::Corrupt(Some(block.index), what);
+    let mut bits = BitReader::new(&block.payload);
+    let symbols = match (static_table, header.method) {
+        (Some(table), _) => (0..block.symbols).map(|_| table.decode(&mut bits)).collect(),
+        (None, header::METHOD_ADAPTIVE) => {
+            let lengths = read_code_lengths(&mut bits).ok_or(BhuffError::BadTree)?;
+            let table = LookupTable::new(&canonical_codes(&lengths));
+            (0..block.symbols).map(|_| table.decode(&mut bits)).collect()
+        }
+        (None, header::METHOD_RANGE0) => range::decode(0, &block.payload, block.symbols),
+        (None, header::METHOD_RANGE1) => range::decode(1, &block.payload, block.symbols),
+        (None, _) => {
+            let tables = tables::read_tables(&mut bits).ok_or(BhuffError::BadTree)?;
+            let selectors = tables::read_selectors(&mut bits, tables.len(), block.symbols).ok_or(corrupt("invalid selector"))?;
+            tables::decode(&mut bits, &tables, &selectors, block.symbols)
+        }
+    };
+    let symbols: Vec<u16> = symbols.ok_or(corrupt("invalid Huffman code"))?;
+    let data = header.pipeline.untransform(&block.params, symbols.into_iter(), block.length).ok_or(corrupt("wrong length"))?;
+    Ok(Decoded { index: block.index, length: block.length, crc: block.crc, data, produced: 0, running_crc: 0 })
+}
+
+// blocks of a stream with a dynamic tree can only be decoded one after the other
+pub fn decode_dynamic_block(tree: &mut dynamic::Tree, block: Block) -> Result<Decoded> {
+    let data = tree.decode(&block.payload, block.length).ok_or(BhuffError::Corrupt(Some(block.index), "invalid Huffman code"))?;
+    Ok(Decoded { index: block.index, length: block.length, crc: block.crc, data: Box::new(data.into_iter()), produced: 0, running_crc: 0 })
+}
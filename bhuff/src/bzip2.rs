@@ -0,0 +1,593 @@
+/* The bzip2 format, so that files can be exchanged with the stock tool.
+ * https://github.com/dsnet/compress/blob/master/doc/bzip2-format.pdf
+ *
+ * It uses the same stages as .bhf, with some differences in the details:
+ * runs of 4 to 255 equal bytes are shortened before the BWT (RLE1), the BWT
+ * sorts rotations rather than suffixes, MTF only ranks the bytes that occur
+ * in the block, the symbols end with an EOB symbol, and the stream is a single
+ * sequence of bits, so blocks do not start on a byte boundary:
+ *
+ *   header    "BZh" and the level, '1'..'9'
+ *   block     48 bits 0x314159265359
+ *             32 bits CRC of the block (bzip2's own CRC, see crc.rs)
+ *             1 bit, randomised (not supported)
+ *             24 bits orig_ptr, the position of the data among its rotations
+ *             16 bits telling which ranges of 16 bytes occur, and 16 bits for
+ *             each of those ranges telling which bytes occur
+ *             3 bits number of tables (2..=6), 15 bits number of selectors
+ *             the selectors, MTF coded in unary (terminated by a zero bit)
+ *             for every table, the code length of the first symbol in 5
+ *             bits, then for every symbol 10 (+1) or 11 (-1) until 0
+ *             the Huffman coded symbols, switching tables every 50 symbols
+ *   end       48 bits 0x177245385090, 32 bits combined CRC, padding to a byte
+ *
+ * A file can hold several streams, which decompress to their concatenation.
+ */
+
+use std::io;
+use std::io::{Read,Write};
+
+use crate::huffman::{MAX_CODE_LEN,canonical_codes};
+use crate::bitstring::BitReader;
+use crate::lookup::LookupTable;
+use crate::transform::{rotation_transform,move_to_front,unmove_to_front,zero_run_encode,zero_run_decode,BwReverse};
+use crate::block::{BLOCK_UNIT,in_parallel};
+use crate::tables::{self,GROUP_SIZE};
+use crate::decoder::{DECODE_COST,DEFAULT_MEMORY_LIMIT};
+use crate::error::{BhuffError,Result};
+use crate::crc;
+
+pub const MAGIC: [u8; 3] = *b"BZh";
+const BLOCK_MAGIC: u64 = 0x3141_5926_5359;
+const END_MAGIC: u64 = 0x1772_4538_5090;
+
+// the bytes in use, RUNA, RUNB and EOB
+const BZ_ALPHABET: usize = 256 + 2;
+const MAX_TABLES: usize = 6;
+// bzip2 ends a block this far before its size, so that the last run fits
+const BLOCK_SLACK: usize = 19;
+
+/* the largest block that can be valid: its headers, 32767 selectors, the
+ * lengths of 6 tables at up to 39 bits each, and the symbols of a level 9
+ * block with EOB, at up to 20 bits */
+const WINDOW: usize = (48 + 32 + 1 + 24 + 16 + 16 * 16 + 3 + 15 + 32767 * MAX_TABLES
+    + MAX_TABLES * BZ_ALPHABET * 39 + (9 * BLOCK_UNIT + 1) * MAX_CODE_LEN as usize).div_ceil(8) + 8;
+
+/* collects bits, most significant first; blocks are encoded separately and
+ * then joined, as they do not end on a byte boundary */
+#[derive(Default)]
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    // up to 48 bits at a time
+    fn put(&mut self, n: u32, value: u64) {
+        self.acc = self.acc << n | value & ((1 << n) - 1);
+        self.count += n;
+        while self.count >= 8 {
+            self.count -= 8;
+            self.out.push((self.acc >> self.count) as u8);
+        }
+    }
+
+    fn append(&mut self, other: &BitWriter) {
+        for &byte in &other.out {
+            self.put(8, byte as u64);
+        }
+        self.put(other.count, other.acc);
+    }
+
+    // the whole bytes written so far
+    fn take(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.out)
+    }
+
+    fn pad(&mut self) {
+        self.put((8 - self.count) % 8, 0);
+    }
+}
+
+// the number of tables that bzip2 uses for this many symbols
+fn table_count(symbols: usize) -> usize {
+    match symbols {
+        0..200     => 2,
+        200..600   => 3,
+        600..1200  => 4,
+        1200..2400 => 5,
+        _          => MAX_TABLES,
+    }
+}
+
+/* As in tables.rs, every table starts out cheap for a range of symbols, and is
+ * then rebuilt from the groups that select it; but bzip2 needs a code for
+ * every symbol of the alphabet in every table. */
+fn choose_tables(block: &[u16], alpha_size: usize) -> (Vec<[u8; BZ_ALPHABET]>, Vec<u8>) {
+    let mut freq = [0; BZ_ALPHABET];
+    for &sym in block {
+        freq[sym as usize] += 1;
+    }
+    let (tables, selectors, _) = tables::optimize(block, &freq[..alpha_size], &[true; BZ_ALPHABET][..alpha_size], table_count(block.len()));
+    (tables, selectors)
+}
+
+fn encode_block(data: &[u8], block_crc: u32) -> BitWriter {
+    let (orig_ptr, mut bwt) = rotation_transform(data);
+    let mut in_use = [false; 256];
+    for &byte in data {
+        in_use[byte as usize] = true;
+    }
+    // MTF ranks only the bytes in use, which are numbered in order
+    let mut rank = [0; 256];
+    let mut n_in_use = 0;
+    for byte in 0..256 {
+        if in_use[byte] {
+            rank[byte] = n_in_use as u8;
+            n_in_use += 1;
+        }
+    }
+    for byte in bwt.iter_mut() {
+        *byte = rank[*byte as usize];
+    }
+    move_to_front(&mut bwt);
+    let alpha_size = n_in_use + 2;
+    let mut symbols = zero_run_encode(&bwt);
+    symbols.push(alpha_size as u16 - 1);
+    let (tables, selectors) = choose_tables(&symbols, alpha_size);
+
+    let mut bits = BitWriter::default();
+    bits.put(48, BLOCK_MAGIC);
+    bits.put(32, block_crc as u64);
+    bits.put(1, 0);
+    bits.put(24, orig_ptr as u64);
+    let ranges: Vec<&[bool]> = in_use.chunks(16).collect();
+    bits.put(16, ranges.iter().fold(0, |map, range| map << 1 | range.contains(&true) as u64));
+    for range in ranges.iter().filter(|range| range.contains(&true)) {
+        bits.put(16, range.iter().fold(0, |map, &used| map << 1 | used as u64));
+    }
+
+    bits.put(3, tables.len() as u64);
+    bits.put(15, selectors.len() as u64);
+    for index in tables::move_to_front(&selectors, tables.len()) {
+        bits.put(index as u32 + 1, ((1 << index) - 1) << 1);
+    }
+    for lengths in &tables {
+        let mut current = lengths[0];
+        bits.put(5, current as u64);
+        for &len in &lengths[..alpha_size] {
+            while current != len {
+                bits.put(2, if current < len { 0b10 } else { 0b11 });
+                current = if current < len { current + 1 } else { current - 1 };
+            }
+            bits.put(1, 0);
+        }
+    }
+
+    let codes: Vec<Vec<(u32, u64)>> = tables.iter().map(|lengths| {
+        let codes = canonical_codes(&lengths[..alpha_size]);
+        (0..alpha_size as u16).map(|sym| {
+            let code = codes[&sym];
+            (code.count() as u32, code.fold(0, |acc, b| acc << 1 | b as u64))
+        }).collect()
+    }).collect();
+    for (group, &t) in symbols.chunks(GROUP_SIZE).zip(&selectors) {
+        for &sym in group {
+            let (len, code) = codes[t as usize][sym as usize];
+            bits.put(len, code);
+        }
+    }
+    bits
+}
+
+/* Compresses everything written to it into a .bz2 stream, like BhuffEncoder.
+ *
+ * Blocks are filled with the output of RLE1 as the data comes in, and are
+ * encoded in batches.
+ */
+pub struct Bz2Encoder<W: Write> {
+    output: Option<W>,
+    level: usize,
+    threads: usize,
+    run: (u8, usize),
+    block: Vec<u8>,
+    block_crc: u32,
+    batch: Vec<(Vec<u8>, u32)>,
+    bits: BitWriter,
+    stream_crc: u32,
+    started: bool,
+}
+
+impl<W: Write> Bz2Encoder<W> {
+    pub fn new(output: W) -> Self {
+        Bz2Encoder {
+            output: Some(output),
+            level: 9,
+            threads: 1,
+            run: (0, 0),
+            block: Vec::new(),
+            block_crc: 0,
+            batch: Vec::new(),
+            bits: BitWriter::default(),
+            stream_crc: 0,
+            started: false,
+        }
+    }
+
+    // block size of 100k..900k; other levels are clamped to 1..=9
+    pub fn level(mut self, level: usize) -> Self {
+        self.level = level.clamp(1, 9);
+        self
+    }
+
+    // number of blocks that are encoded in parallel
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    fn output(&mut self) -> &mut W {
+        self.output.as_mut().expect("encoder used after finish")
+    }
+
+    // a run of up to 255 bytes goes into the current block, which ends when it is full
+    fn end_run(&mut self) -> io::Result<()> {
+        let (byte, len) = self.run;
+        if len == 0 {
+            return Ok(());
+        }
+        self.run.1 = 0;
+        self.block.extend(std::iter::repeat_n(byte, len.min(4)));
+        if len >= 4 {
+            self.block.push((len - 4) as u8);
+        }
+        for _ in 0..len {
+            self.block_crc = crc::bzip2_update(self.block_crc, &[byte]);
+        }
+        if self.block.len() >= self.level * BLOCK_UNIT - BLOCK_SLACK {
+            self.end_block(false)?;
+        }
+        Ok(())
+    }
+
+    // encodes the batch once it is complete, or if asked to
+    fn end_block(&mut self, partial: bool) -> io::Result<()> {
+        if !self.block.is_empty() {
+            self.batch.push((std::mem::take(&mut self.block), self.block_crc));
+            self.block_crc = 0;
+        }
+        if !partial && self.batch.len() < self.threads {
+            return Ok(());
+        }
+        if !self.started {
+            self.started = true;
+            self.bits.put(32, u32::from_be_bytes([MAGIC[0], MAGIC[1], MAGIC[2], b'0' + self.level as u8]) as u64);
+        }
+        let batch = std::mem::take(&mut self.batch);
+        for (block, block_crc) in in_parallel(batch, |(data, block_crc)| (encode_block(&data, block_crc), block_crc)) {
+            self.bits.append(&block);
+            self.stream_crc = crc::combine(self.stream_crc, block_crc);
+        }
+        let bytes = self.bits.take();
+        self.output().write_all(&bytes)
+    }
+
+    fn write_trailer(&mut self) -> io::Result<()> {
+        self.end_run()?;
+        self.end_block(true)?;
+        self.bits.put(48, END_MAGIC);
+        self.bits.put(32, self.stream_crc as u64);
+        self.bits.pad();
+        let bytes = self.bits.take();
+        let output = self.output();
+        output.write_all(&bytes)?;
+        output.flush()
+    }
+
+    // completes the stream and returns the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_trailer()?;
+        Ok(self.output.take().unwrap())
+    }
+}
+
+impl<W: Write> Write for Bz2Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            if self.run.1 > 0 && (byte != self.run.0 || self.run.1 == 255) {
+                self.end_run()?;
+            }
+            self.run = (byte, self.run.1 + 1);
+        }
+        Ok(buf.len())
+    }
+
+    // a flush ends the current block early; the last few bits only go out with the next block
+    fn flush(&mut self) -> io::Result<()> {
+        self.end_run()?;
+        self.end_block(true)?;
+        self.output().flush()
+    }
+}
+
+impl<W: Write> Drop for Bz2Encoder<W> {
+    fn drop(&mut self) {
+        if self.output.is_some() {
+            let _ = self.write_trailer();
+        }
+    }
+}
+
+/* undoes RLE1 while the data is produced: after 4 equal bytes comes the
+ * number of times the byte is repeated further */
+struct Runs {
+    data: BwReverse,
+    last: u8,
+    count: usize,
+    repeat: usize,
+}
+
+impl Iterator for Runs {
+    type Item = u8;
+    fn next(&mut self) -> Option<u8> {
+        if self.repeat > 0 {
+            self.repeat -= 1;
+            return Some(self.last);
+        }
+        loop {
+            let byte = self.data.next()?;
+            if self.count == 4 {
+                self.count = 0;
+                if byte == 0 {
+                    continue;
+                }
+                self.repeat = byte as usize - 1;
+                return Some(self.last);
+            }
+            self.count = if byte == self.last && self.count > 0 { self.count + 1 } else { 1 };
+            self.last = byte;
+            return Some(byte);
+        }
+    }
+}
+
+struct Block {
+    index: usize,
+    crc: u32,
+    data: Runs,
+    running_crc: u32,
+}
+
+fn get(bits: &mut BitReader, n: u32) -> Result<u32> {
+    let value = bits.peek(n);
+    bits.consume(n);
+    if bits.overrun() { Err(BhuffError::Truncated) } else { Ok(value) }
+}
+
+fn get_magic(bits: &mut BitReader) -> Result<u64> {
+    Ok((get(bits, 24)? as u64) << 24 | get(bits, 24)? as u64)
+}
+
+// reads a block up to the inverse BWT, or returns None at the end of the stream
+fn read_block(bits: &mut BitReader, index: usize, level: usize) -> Result<Option<(u32, BwReverse)>> {
+    let corrupt = |what| BhuffError::Corrupt(Some(index), what);
+    match get_magic(bits)? {
+        BLOCK_MAGIC => {}
+        END_MAGIC => return Ok(None),
+        _ => return Err(corrupt("bad block header")),
+    }
+    let block_crc = get(bits, 32)?;
+    if get(bits, 1)? != 0 {
+        return Err(corrupt("randomised blocks are not supported"));
+    }
+    let orig_ptr = get(bits, 24)? as usize;
+
+    let ranges = get(bits, 16)?;
+    let mut in_use = Vec::new();
+    for range in (0..16).filter(|range| ranges & 0x8000 >> range != 0) {
+        let map = get(bits, 16)?;
+        in_use.extend((0..16).filter(|i| map & 0x8000 >> i != 0).map(|i| (range * 16 + i) as u8));
+    }
+    if in_use.is_empty() {
+        return Err(corrupt("no bytes in use"));
+    }
+    let alpha_size = in_use.len() + 2;
+
+    let ntables = get(bits, 3)? as usize;
+    if !(2..=MAX_TABLES).contains(&ntables) {
+        return Err(corrupt("invalid number of tables"));
+    }
+    let nselectors = get(bits, 15)? as usize;
+    let mut order: Vec<usize> = (0..ntables).collect();
+    let selectors = (0..nselectors).map(|_| {
+        let mut index = 0;
+        while get(bits, 1)? != 0 {
+            index += 1;
+            if index == ntables {
+                return Err(corrupt("invalid selector"));
+            }
+        }
+        let t = order.remove(index);
+        order.insert(0, t);
+        Ok(t)
+    }).collect::<Result<Vec<_>>>()?;
+
+    let mut tables = Vec::new();
+    for _ in 0..ntables {
+        let mut current = get(bits, 5)?;
+        let mut lengths = Vec::with_capacity(alpha_size);
+        for _ in 0..alpha_size {
+            loop {
+                if !(1..=MAX_CODE_LEN as u32).contains(&current) {
+                    return Err(BhuffError::BadTree);
+                }
+                match get(bits, 1)? {
+                    0 => break,
+                    _ => if get(bits, 1)? == 0 { current += 1 } else { current -= 1 },
+                }
+            }
+            lengths.push(current as u8);
+        }
+        // unused codes are allowed, but too many codes would overflow
+        let kraft_sum: u32 = lengths.iter().map(|&len| 1 << (MAX_CODE_LEN - len)).sum();
+        if kraft_sum > 1 << MAX_CODE_LEN {
+            return Err(BhuffError::BadTree);
+        }
+        tables.push(LookupTable::new(&canonical_codes(&lengths)));
+    }
+
+    // every symbol stands for at least one byte
+    let max_len = level * BLOCK_UNIT;
+    let mut symbols = Vec::new();
+    loop {
+        let table = selectors.get(symbols.len() / GROUP_SIZE).ok_or(corrupt("invalid selector"))?;
+        let sym = match tables[*table].decode(bits) {
+            Some(sym) => sym,
+            None if bits.overrun() => return Err(BhuffError::Truncated),
+            None => return Err(corrupt("invalid Huffman code")),
+        };
+        if sym as usize == alpha_size - 1 {
+            break;
+        }
+        symbols.push(sym);
+        if symbols.len() > max_len {
+            return Err(corrupt("block too large"));
+        }
+    }
+    let mut data = zero_run_decode(symbols.into_iter(), max_len).ok_or(corrupt("block too large"))?;
+    unmove_to_front(&mut data);
+    for byte in data.iter_mut() {
+        *byte = in_use[*byte as usize];
+    }
+    let data = BwReverse::rotations(orig_ptr, &data).ok_or(corrupt("start position out of range"))?;
+    Ok(Some((block_crc, data)))
+}
+
+/* Decompresses a .bz2 file while it is being read, one block at a time.
+ *
+ * The input is kept in a window that holds at least the largest possible
+ * block, so a block can be decoded without knowing where it ends.
+ */
+pub struct Bz2Decoder<R: Read> {
+    input: R,
+    memory_limit: usize,
+    window: Vec<u8>,
+    bit_pos: usize,
+    eof: bool,
+    level: usize,
+    blocks: usize,
+    stream_crc: u32,
+    streams: usize,
+    current: Option<Block>,
+    done: bool,
+}
+
+impl<R: Read> Bz2Decoder<R> {
+    pub fn new(input: R) -> Self {
+        Bz2Decoder {
+            input,
+            memory_limit: DEFAULT_MEMORY_LIMIT,
+            window: Vec::new(),
+            bit_pos: 0,
+            eof: false,
+            level: 0,
+            blocks: 0,
+            stream_crc: 0,
+            streams: 0,
+            current: None,
+            done: false,
+        }
+    }
+
+    // blocks that would need more than this many bytes are not decoded
+    pub fn memory_limit(mut self, bytes: usize) -> Self {
+        self.memory_limit = bytes;
+        self
+    }
+
+    fn refill(&mut self) -> Result<()> {
+        self.window.drain(..self.bit_pos / 8);
+        self.bit_pos %= 8;
+        let want = WINDOW.saturating_sub(self.window.len());
+        if !self.eof && want > 0 {
+            let n = self.input.by_ref().take(want as u64).read_to_end(&mut self.window)?;
+            self.eof = n < want;
+        }
+        Ok(())
+    }
+
+    // reads the next block, or the header or end of a stream
+    fn next_block(&mut self) -> Result<()> {
+        self.refill()?;
+        if self.level == 0 {
+            if self.streams > 0 && self.window.is_empty() {
+                self.done = true;
+                return Ok(());
+            }
+            match self.window[..self.window.len().min(4)] {
+                [b'B', b'Z', b'h', level @ b'1'..=b'9'] => self.level = (level - b'0') as usize,
+                _ => return Err(BhuffError::BadMagic),
+            }
+            let cost = DECODE_COST * self.level * BLOCK_UNIT + WINDOW;
+            if cost > self.memory_limit {
+                return Err(BhuffError::MemoryLimit(cost));
+            }
+            (self.bit_pos, self.blocks, self.stream_crc) = (32, 0, 0);
+            self.streams += 1;
+            return Ok(());
+        }
+
+        let window = &self.window[self.bit_pos / 8..];
+        let mut bits = BitReader::new(window);
+        let skip = (self.bit_pos % 8) as u32;
+        if skip > 0 {
+            get(&mut bits, skip)?;
+        }
+        let block = read_block(&mut bits, self.blocks + 1, self.level);
+        // the bits read, counted from the start of the byte the block started in
+        let start = self.bit_pos / 8 * 8;
+        let consumed = window.len() * 8 - bits.remaining();
+        match block? {
+            Some((crc, data)) => {
+                self.blocks += 1;
+                self.stream_crc = crc::combine(self.stream_crc, crc);
+                let data = Runs { data, last: 0, count: 0, repeat: 0 };
+                self.current = Some(Block { index: self.blocks, crc, data, running_crc: 0 });
+                self.bit_pos = start + consumed;
+            }
+            None => {
+                if get(&mut bits, 32)? != self.stream_crc {
+                    return Err(BhuffError::Corrupt(None, "stream CRC mismatch"));
+                }
+                let consumed = window.len() * 8 - bits.remaining();
+                // the next stream starts on a byte boundary
+                self.bit_pos = (start + consumed).div_ceil(8) * 8;
+                self.level = 0;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Bz2Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match &mut self.current {
+                Some(block) => {
+                    let n = buf.iter_mut().zip(&mut block.data).map(|(slot, byte)| *slot = byte).count();
+                    block.running_crc = crc::bzip2_update(block.running_crc, &buf[..n]);
+                    if n > 0 || buf.is_empty() {
+                        return Ok(n);
+                    }
+                    if block.running_crc != block.crc {
+                        return Err(BhuffError::Corrupt(Some(block.index), "CRC mismatch").into());
+                    }
+                    self.current = None;
+                }
+                None if self.done => return Ok(0),
+                None => self.next_block()?,
+            }
+        }
+    }
+}
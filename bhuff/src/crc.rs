@@ -0,0 +1,56 @@
+/* CRC-32 as used by zlib, PNG, etc. (reflected polynomial 0xEDB88320)
+ * https://en.wikipedia.org/wiki/Cyclic_redundancy_check
+ */
+
+const POLY: u32 = 0xEDB8_8320;
+
+const TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { crc >> 1 ^ POLY } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+pub fn crc32(data: &[u8]) -> u32 {
+    update(0, data)
+}
+
+// continues a CRC computed over the preceding data
+pub fn update(crc: u32, data: &[u8]) -> u32 {
+    !data.iter().fold(!crc, |crc, &byte| crc >> 8 ^ TABLE[(crc as u8 ^ byte) as usize])
+}
+
+/* bzip2 uses the same polynomial unreflected, i.e. most significant bit first */
+const BZIP2_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 << 31 != 0 { crc << 1 ^ POLY.reverse_bits() } else { crc << 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+pub fn bzip2_update(crc: u32, data: &[u8]) -> u32 {
+    !data.iter().fold(!crc, |crc, &byte| crc << 8 ^ BZIP2_TABLE[((crc >> 24) as u8 ^ byte) as usize])
+}
+
+// the stream CRC combines the CRCs of its blocks as in bzip2
+pub fn combine(stream_crc: u32, block_crc: u32) -> u32 {
+    stream_crc.rotate_left(1) ^ block_crc
+}
@@ -0,0 +1,207 @@
+use std::io;
+use std::collections::VecDeque;
+use std::io::{BufRead,Read};
+
+use crate::huffman::{HUFFTREE,codes};
+use crate::model::Model;
+use crate::block::{Decoded,in_parallel,read_block,decode_block,decode_dynamic_block};
+use crate::dynamic;
+use crate::lookup::LookupTable;
+use crate::header::{self,Header,get_u32};
+use crate::error::{BhuffError,Result};
+use crate::crc;
+use crate::pipeline::Pipeline;
+
+/* Memory used while decoding a block, per byte of block size: the payload (up
+ * to 7 bytes with range coding), the decoded symbols and their zero-run
+ * expansion, and then the links of the inverse BWT (a u32 per byte), which
+ * are kept until the block has been read.
+ * The limit can be raised or lowered with memory_limit().
+ */
+pub const DECODE_COST: usize = 12;
+pub const DEFAULT_MEMORY_LIMIT: usize = 256 << 20;
+
+struct Stream {
+    header: Header,
+    threads: usize,
+    blocks: usize,
+    crc: u32,
+    tree: dynamic::Tree,
+}
+
+/* Decompresses a .bhf stream while it is being read. Concatenated streams
+ * decompress to the concatenation of their contents, as with gzip.
+ *
+ * Errors are reported as io::Error; they convert back into a BhuffError.
+ */
+pub struct BhuffDecoder<R: Read> {
+    input: io::BufReader<R>,
+    threads: usize,
+    memory_limit: usize,
+    builtin_table: LookupTable,
+    model: Option<(u32, LookupTable)>,
+    stream: Option<Stream>,
+    streams: usize,
+    pending: VecDeque<Decoded>,
+    done: bool,
+}
+
+impl<R: Read> BhuffDecoder<R> {
+    pub fn new(input: R) -> Self {
+        BhuffDecoder {
+            input: io::BufReader::new(input),
+            threads: 1,
+            memory_limit: DEFAULT_MEMORY_LIMIT,
+            builtin_table: LookupTable::new(&codes(HUFFTREE)),
+            model: None,
+            stream: None,
+            streams: 0,
+            pending: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    // number of blocks that are decoded in parallel
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    // blocks are decoded in fewer threads, or not at all, to stay within this many bytes
+    pub fn memory_limit(mut self, bytes: usize) -> Self {
+        self.memory_limit = bytes;
+        self
+    }
+
+    // the model to decode streams that were compressed with one
+    pub fn model(mut self, model: &Model) -> Self {
+        self.model = Some((model.hash(), LookupTable::new(&model.codes())));
+        self
+    }
+
+    pub fn into_inner(self) -> R {
+        self.input.into_inner()
+    }
+
+    // decodes the next batch of blocks, up to the point where their data can be read
+    fn fill(&mut self) -> Result<()> {
+        let stream = match &mut self.stream {
+            Some(stream) => stream,
+            None => {
+                if self.streams > 0 && self.input.fill_buf()?.is_empty() {
+                    self.done = true;
+                    return Ok(());
+                }
+                let header = Header::read(&mut self.input)?;
+                if header.method == header::METHOD_MODEL && self.model.as_ref().is_none_or(|model| model.0 != header.model) {
+                    return Err(BhuffError::WrongModel(header.model));
+                }
+                // some stages can make the block longer
+                let block_cost = DECODE_COST * header.pipeline.max_len(header.block_size);
+                // a dynamic tree has to be updated block by block
+                let threads = if header.method == header::METHOD_DYNAMIC { 1 } else { self.threads };
+                let threads = threads.min(self.memory_limit / block_cost);
+                if threads == 0 {
+                    return Err(BhuffError::MemoryLimit(block_cost));
+                }
+                self.streams += 1;
+                self.stream.insert(Stream { header, threads, blocks: 0, crc: 0, tree: dynamic::Tree::new() })
+            }
+        };
+
+        let mut batch = Vec::new();
+        let mut ended = false;
+        while batch.len() < stream.threads {
+            match read_block(&mut self.input, stream.blocks + 1, &stream.header)? {
+                Some(block) => {
+                    stream.blocks += 1;
+                    batch.push(block);
+                }
+                None => {
+                    ended = true;
+                    break;
+                }
+            }
+        }
+
+        let method = stream.header.method;
+        let table = match method {
+            header::METHOD_BUILTIN => Some(&self.builtin_table),
+            header::METHOD_MODEL   => self.model.as_ref().map(|model| &model.1),
+            _ => None,
+        };
+        // the stored block CRCs are checked against the data as it is read
+        for block in &batch {
+            stream.crc = crc::combine(stream.crc, block.crc);
+        }
+        if method == header::METHOD_DYNAMIC {
+            for block in batch {
+                self.pending.push_back(decode_dynamic_block(&mut stream.tree, block)?);
+            }
+        } else {
+            let header = &stream.header;
+            for result in in_parallel(batch, |block| decode_block(header, table, block)) {
+                self.pending.push_back(result?);
+            }
+        }
+
+        if ended {
+            if get_u32(&mut self.input)? != stream.crc {
+                return Err(BhuffError::Corrupt(None, "stream CRC mismatch"));
+            }
+            self.stream = None;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for BhuffDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.pending.front_mut() {
+                Some(block) => match block.read(buf)? {
+                    0 if !buf.is_empty() => { self.pending.pop_front(); }
+                    n => return Ok(n),
+                },
+                None if self.done => return Ok(0),
+                None => self.fill()?,
+            }
+        }
+    }
+}
+
+pub struct Summary {
+    pub method: &'static str,
+    // None for method 6, which has no transforms
+    pub pipeline: Option<Pipeline>,
+    pub block_size: usize,
+    pub blocks: usize,
+    pub uncompressed: u64,
+}
+
+// walks the block structure of the streams without decoding anything
+pub fn list(mut input: impl BufRead) -> Result<Summary> {
+    let mut header = Header::read(&mut input)?;
+    let method = match header.method {
+        header::METHOD_BUILTIN => "static",
+        header::METHOD_MODEL   => "model",
+        header::METHOD_TABLES  => "tables",
+        header::METHOD_RANGE0  => "range0",
+        header::METHOD_RANGE1  => "range1",
+        header::METHOD_DYNAMIC => "dynamic",
+        _ => "adaptive",
+    };
+    let pipeline = (header.method != header::METHOD_DYNAMIC).then_some(header.pipeline);
+    let mut summary = Summary { method, pipeline, block_size: header.block_size, blocks: 0, uncompressed: 0 };
+    loop {
+        while let Some(block) = read_block(&mut input, summary.blocks + 1, &header)? {
+            summary.blocks += 1;
+            summary.uncompressed += block.length as u64;
+        }
+        get_u32(&mut input)?;
+        if input.fill_buf()?.is_empty() {
+            break Ok(summary);
+        }
+        header = Header::read(&mut input)?;
+    }
+}
@@ -0,0 +1,220 @@
+/* One-pass adaptive Huffman coding (FGK), for data that cannot be collected
+ * into blocks: the tree starts out empty and is updated after every byte, by
+ * the encoder and the decoder alike. A byte that has not been seen before is
+ * sent as the code of the NYT ("not yet transmitted") leaf, followed by the
+ * byte itself.
+ * D.E. Knuth: "Dynamic Huffman coding"
+ * https://doi.org/10.1016/0196-6774(85)90036-7
+ *
+ * Nodes are numbered by their index in a vector, with the root at 0; the tree
+ * keeps the sibling property, i.e. weights never increase with the index and
+ * siblings are neighbours. To keep codes short, all weights are halved once
+ * the root reaches MAX_WEIGHT, and the tree is rebuilt.
+ */
+
+use crate::huffman::{BitString,bits_to_bytes,get_bits};
+use crate::bitstring::{Bits,BitReader};
+
+const NYT: u16 = 256;
+const MAX_WEIGHT: u64 = 1 << 16;
+const NONE: usize = usize::MAX;
+
+// small blocks, so that little has to be buffered
+pub const BLOCK_SIZE: usize = 16 << 10;
+
+// a code never gets near 64 bits with these weights; a new byte adds 8 more
+pub fn max_payload(length: usize) -> usize {
+    length * 9
+}
+
+// these are the BTree nodes, with indices instead of references
+#[derive(Debug,Clone,Copy)]
+enum Kind {
+    Tip(u16),
+    Bin(usize, usize),
+}
+
+#[derive(Debug,Clone,Copy)]
+struct Node {
+    weight: u64,
+    parent: usize,
+    kind: Kind,
+}
+
+pub struct Tree {
+    nodes: Vec<Node>,
+    leaf: [usize; 257],
+}
+
+impl Default for Tree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Tree {
+    pub fn new() -> Self {
+        let mut leaf = [NONE; 257];
+        leaf[NYT as usize] = 0;
+        Tree { nodes: vec![Node { weight: 0, parent: NONE, kind: Kind::Tip(NYT) }], leaf }
+    }
+
+    // the node in slot i has moved, so its children or leaf entry need to know
+    fn relink(&mut self, i: usize) {
+        match self.nodes[i].kind {
+            Kind::Tip(sym)   => self.leaf[sym as usize] = i,
+            Kind::Bin(l, r)  => { self.nodes[l].parent = i; self.nodes[r].parent = i; }
+        }
+    }
+
+    fn swap(&mut self, a: usize, b: usize) {
+        let (ka, kb) = (self.nodes[a].kind, self.nodes[b].kind);
+        self.nodes[a].kind = kb;
+        self.nodes[b].kind = ka;
+        self.relink(a);
+        self.relink(b);
+    }
+
+    fn update(&mut self, byte: u8) {
+        let mut q = self.leaf[byte as usize];
+        if q == NONE {
+            // the NYT leaf becomes the parent of a new NYT leaf and the new symbol
+            let nyt = self.leaf[NYT as usize];
+            let (sym, new_nyt) = (self.nodes.len(), self.nodes.len() + 1);
+            self.nodes.push(Node { weight: 0, parent: nyt, kind: Kind::Tip(byte as u16) });
+            self.nodes.push(Node { weight: 0, parent: nyt, kind: Kind::Tip(NYT) });
+            self.nodes[nyt].kind = Kind::Bin(new_nyt, sym);
+            self.leaf[byte as usize] = sym;
+            self.leaf[NYT as usize] = new_nyt;
+            q = sym;
+        }
+        loop {
+            // move to the front of the nodes of equal weight, unless that is the parent
+            let weight = self.nodes[q].weight;
+            let leader = self.nodes[..q].partition_point(|node| node.weight > weight);
+            if leader != q && leader != self.nodes[q].parent {
+                self.swap(q, leader);
+                q = leader;
+            }
+            self.nodes[q].weight += 1;
+            if q == 0 {
+                break;
+            }
+            q = self.nodes[q].parent;
+        }
+        if self.nodes[0].weight >= MAX_WEIGHT {
+            self.rescale();
+        }
+    }
+
+    /* Halves all weights and builds a Huffman tree anew; listing the merged
+     * pairs in the order they are merged gives the numbering of the nodes. */
+    fn rescale(&mut self) {
+        let mut queue: Vec<(u64, Kind)> = self.nodes.iter()
+            .filter(|node| matches!(node.kind, Kind::Tip(_)))
+            .map(|node| (node.weight.div_ceil(2), node.kind))
+            .collect();
+        queue.sort_by_key(|x| x.0);
+        let mut order: Vec<(u64, Kind)> = Vec::new();
+        while queue.len() > 1 {
+            let (a, b) = (queue.remove(0), queue.remove(0));
+            order.extend([a, b]);
+            let el = (a.0 + b.0, Kind::Bin(order.len() - 2, order.len() - 1));
+            let pos = queue.partition_point(|x| x.0 <= el.0);
+            queue.insert(pos, el);
+        }
+        order.extend(queue);
+
+        let last = order.len() - 1;
+        self.nodes = order.into_iter().rev().map(|(weight, kind)| {
+            let kind = match kind {
+                Kind::Bin(l, r) => Kind::Bin(last - l, last - r),
+                tip => tip,
+            };
+            Node { weight, parent: NONE, kind }
+        }).collect();
+        for i in 0..self.nodes.len() {
+            self.relink(i);
+        }
+    }
+
+    fn code(&self, mut node: usize) -> BitString {
+        let mut path = Vec::new();
+        while node != 0 {
+            let parent = self.nodes[node].parent;
+            path.push(matches!(self.nodes[parent].kind, Kind::Bin(_, r) if r == node));
+            node = parent;
+        }
+        path.iter().rev().fold(BitString::new(), |code, &bit| code.append(bit))
+    }
+
+    pub fn encode(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(data.len());
+        for &byte in data {
+            match self.leaf[byte as usize] {
+                NONE => {
+                    out.push(self.code(self.leaf[NYT as usize]));
+                    out.push(BitString::with_len(byte as usize, 8));
+                }
+                leaf => out.push(self.code(leaf)),
+            }
+            self.update(byte);
+        }
+        bits_to_bytes(out.into_iter().flatten()).collect()
+    }
+
+    pub fn decode(&mut self, payload: &[u8], len: usize) -> Option<Vec<u8>> {
+        let mut bits = BitReader::new(payload);
+        let mut out = Vec::with_capacity(len);
+        for _ in 0..len {
+            let mut node = 0;
+            let byte = loop {
+                match self.nodes[node].kind {
+                    Kind::Bin(l, r) => node = if bits.next()? { r } else { l },
+                    Kind::Tip(NYT)  => break get_bits(&mut bits, 8)?,
+                    Kind::Tip(sym)  => break sym as u8,
+                }
+            };
+            self.update(byte);
+            out.push(byte);
+        }
+        Some(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // short runs of a few byte values, where many nodes have equal weights
+    #[test]
+    fn few_distinct_bytes() {
+        let mut state = 1u32;
+        let mut data = Vec::new();
+        while data.len() < 50000 {
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            let (byte, run) = ((state >> 24) as u8 % 4, (state >> 16) as usize % 3 + 1);
+            data.extend(std::iter::repeat_n(byte, run));
+        }
+        for len in [2, 100, 5000, data.len()] {
+            let payload = Tree::new().encode(&data[..len]);
+            assert_eq!(Tree::new().decode(&payload, len).unwrap(), data[..len]);
+        }
+    }
+
+    // a geometric distribution over 200,000 bytes, so the weights are halved several times
+    #[test]
+    fn rescaled_skewed_bytes() {
+        let mut state = 1u32;
+        let data: Vec<u8> = (0..200_000).map(|_| {
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            (state >> 8).trailing_zeros() as u8
+        }).collect();
+        for len in [MAX_WEIGHT as usize, MAX_WEIGHT as usize + 1, data.len()] {
+            let mut encoder = Tree::new();
+            let payload = encoder.encode(&data[..len]);
+            assert!(encoder.nodes[0].weight < len as u64);
+            assert_eq!(Tree::new().decode(&payload, len).unwrap(), data[..len]);
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use std::io;
+use std::io::Write;
+use std::collections::HashMap;
+
+use crate::huffman::{BitString,HUFFTREE,codes};
+use crate::model::Model;
+use crate::block::{BLOCK_UNIT,in_parallel,encode_block,encode_dynamic_block};
+use crate::dynamic;
+use crate::header::{self,Header};
+use crate::crc;
+use crate::rank::RANKINGS;
+use crate::pipeline::{Pipeline,is_rank};
+use crate::error::{BhuffError,Result};
+
+/* Compresses everything written to it into a .bhf stream.
+ *
+ * Data is collected until a batch of full blocks can be encoded; the stream is
+ * completed by finish(), or when the encoder is dropped (ignoring errors).
+ *
+ *   let mut enc = BhuffEncoder::new(file).level(6);
+ *   enc.write_all(data)?;
+ *   let file = enc.finish()?;
+ */
+pub struct BhuffEncoder<W: Write> {
+    output: Option<W>,
+    block_size: usize,
+    threads: usize,
+    method: u8,
+    pipeline: Pipeline,
+    model_hash: u32,
+    static_codes: Option<HashMap<u16,BitString>>,
+    tree: Option<dynamic::Tree>,
+    buffer: Vec<u8>,
+    stream_crc: u32,
+    started: bool,
+}
+
+impl<W: Write> BhuffEncoder<W> {
+    pub fn new(output: W) -> Self {
+        BhuffEncoder {
+            output: Some(output),
+            block_size: 9 * BLOCK_UNIT,
+            threads: 1,
+            method: header::METHOD_TABLES,
+            pipeline: Pipeline::default(),
+            model_hash: 0,
+            static_codes: None,
+            tree: None,
+            buffer: Vec::new(),
+            stream_crc: 0,
+            started: false,
+        }
+    }
+
+    // block size of 100k..900k, as in bzip2; other levels are clamped to 1..=9
+    pub fn level(mut self, level: usize) -> Self {
+        self.block_size = level.clamp(1, 9) * BLOCK_UNIT;
+        self
+    }
+
+    // number of blocks that are encoded in parallel
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    // the transforms that blocks go through; BWT and move-to-front by default
+    pub fn pipeline(mut self, pipeline: Pipeline) -> Self {
+        self.pipeline = pipeline;
+        self
+    }
+
+    // use the bijective BWT, which saves storing a start position for every block
+    pub fn bijective(mut self, enable: bool) -> Self {
+        let bwt = if enable { header::STAGE_BWTS } else { header::STAGE_BWT };
+        self.pipeline = self.pipeline.replace(|stage| stage <= header::STAGE_BWTS, bwt);
+        self
+    }
+
+    // the stage after the BWT, by one of the names in RANKINGS; "mtf" by default
+    pub fn ranking(mut self, name: &str) -> Result<Self> {
+        let &(_, rank) = RANKINGS.iter().find(|&&(known, _)| known == name)
+            .ok_or_else(|| BhuffError::Usage(format!("unknown ranking {name}")))?;
+        self.pipeline = self.pipeline.replace(is_rank, header::STAGE_MTF + rank);
+        Ok(self)
+    }
+
+    // use the built-in Huffman tree instead of storing a tree in every block
+    pub fn static_tree(mut self, enable: bool) -> Self {
+        self.method = if enable { header::METHOD_BUILTIN } else { header::METHOD_TABLES };
+        self.static_codes = enable.then(|| codes(HUFFTREE));
+        self.tree = None;
+        self
+    }
+
+    // use adaptive range coding instead of Huffman coding; order 0, or 1 for anything higher
+    pub fn range_coder(mut self, order: usize) -> Self {
+        self.method = if order == 0 { header::METHOD_RANGE0 } else { header::METHOD_RANGE1 };
+        self.static_codes = None;
+        self.tree = None;
+        self
+    }
+
+    /* one-pass adaptive Huffman coding without a transform, in small blocks,
+     * for when data has to go out as soon as it comes in; compresses less */
+    pub fn dynamic(mut self) -> Self {
+        self.method = header::METHOD_DYNAMIC;
+        self.block_size = dynamic::BLOCK_SIZE;
+        self.static_codes = None;
+        self.tree = Some(dynamic::Tree::new());
+        self
+    }
+
+    // use the tree of a model file for every block; decoding needs the same model
+    pub fn model(mut self, model: &Model) -> Self {
+        self.method = header::METHOD_MODEL;
+        self.model_hash = model.hash();
+        self.static_codes = Some(model.codes());
+        self.tree = None;
+        self
+    }
+
+    fn batch_size(&self) -> usize {
+        if self.tree.is_some() { self.block_size } else { self.block_size * self.threads }
+    }
+
+    fn output(&mut self) -> &mut W {
+        self.output.as_mut().expect("encoder used after finish")
+    }
+
+    fn header(&self) -> Header {
+        Header::new(self.method, self.pipeline, self.block_size, self.model_hash)
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.started {
+            self.started = true;
+            let header = self.header().to_bytes();
+            self.output().write_all(&header)?;
+        }
+        Ok(())
+    }
+
+    // encodes the buffered data; a partial batch only if asked to
+    fn encode(&mut self, partial: bool) -> io::Result<()> {
+        self.write_header()?;
+        while self.buffer.len() >= self.batch_size() || (partial && !self.buffer.is_empty()) {
+            let take = self.buffer.len().min(self.batch_size());
+            let batch: Vec<Vec<u8>> = self.buffer[..take].chunks(self.block_size).map(<[u8]>::to_vec).collect();
+            self.buffer.drain(..take);
+            let (header, codes) = (self.header(), self.static_codes.as_ref());
+            let blocks = match &mut self.tree {
+                Some(tree) => batch.iter().map(|data| encode_dynamic_block(tree, data)).collect(),
+                None => in_parallel(batch, |data| encode_block(&header, codes, data)),
+            };
+            for (block, block_crc) in blocks {
+                self.output().write_all(&block)?;
+                self.stream_crc = crc::combine(self.stream_crc, block_crc);
+            }
+        }
+        Ok(())
+    }
+
+    fn write_trailer(&mut self) -> io::Result<()> {
+        self.encode(true)?;
+        let stream_crc = self.stream_crc;
+        let output = self.output();
+        output.write_all(&[0])?;
+        output.write_all(&stream_crc.to_be_bytes())?;
+        output.flush()
+    }
+
+    // completes the stream and returns the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_trailer()?;
+        Ok(self.output.take().unwrap())
+    }
+}
+
+impl<W: Write> Write for BhuffEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(self.batch_size() - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..n]);
+        if self.buffer.len() == self.batch_size() {
+            self.encode(false)?;
+        }
+        Ok(n)
+    }
+
+    // a flush ends the current block early, which costs some compression
+    fn flush(&mut self) -> io::Result<()> {
+        self.encode(true)?;
+        self.output().flush()
+    }
+}
+
+impl<W: Write> Drop for BhuffEncoder<W> {
+    fn drop(&mut self) {
+        if self.output.is_some() {
+            let _ = self.write_trailer();
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum BhuffError {
+    Usage(String),
+    Io(io::Error),
+    BadMagic,
+    UnsupportedVersion(u8),
+    UnsupportedMethod(u8),
+    UnsupportedFlags(u8),
+    Truncated,
+    Corrupt(Option<usize>, &'static str),
+    BadTree,
+    MemoryLimit(usize),
+    WrongModel(u32),
+}
+
+pub type Result<T> = std::result::Result<T, BhuffError>;
+
+impl BhuffError {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            BhuffError::Usage(_)              => 2,
+            BhuffError::Io(_)                 => 3,
+            BhuffError::BadMagic              => 4,
+            BhuffError::UnsupportedVersion(_) |
+            BhuffError::UnsupportedMethod(_)  |
+            BhuffError::UnsupportedFlags(_)   => 5,
+            BhuffError::Truncated             => 6,
+            BhuffError::Corrupt(..)           => 7,
+            BhuffError::BadTree               => 8,
+            BhuffError::MemoryLimit(_)        => 9,
+            BhuffError::WrongModel(_)         => 10,
+        }
+    }
+}
+
+impl fmt::Display for BhuffError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BhuffError::Usage(msg)               => write!(f, "{msg}"),
+            BhuffError::Io(err)                  => write!(f, "{err}"),
+            BhuffError::BadMagic                 => write!(f, "not a bhuff file"),
+            BhuffError::UnsupportedVersion(v)    => write!(f, "unsupported format version {v}"),
+            BhuffError::UnsupportedMethod(m)     => write!(f, "unsupported method {m}"),
+            BhuffError::UnsupportedFlags(x)      => write!(f, "unsupported flags {x:#04x}"),
+            BhuffError::Truncated                => write!(f, "unexpected end of input"),
+            BhuffError::Corrupt(Some(block), what) => write!(f, "block {block}: {what}"),
+            BhuffError::Corrupt(None, what)      => write!(f, "{what}"),
+            BhuffError::BadTree                  => write!(f, "invalid Huffman tree"),
+            BhuffError::MemoryLimit(n)           => write!(f, "decoding needs {} MiB, more than the memory limit", n.div_ceil(1 << 20)),
+            BhuffError::WrongModel(hash)         => write!(f, "needs the model with hash {hash:08x}"),
+        }
+    }
+}
+
+impl std::error::Error for BhuffError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BhuffError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for BhuffError {
+    fn from(err: io::Error) -> Self {
+        // errors that passed through a Read or Write impl are unwrapped again
+        if err.get_ref().is_some_and(|inner| inner.is::<BhuffError>()) {
+            return *err.into_inner().unwrap().downcast::<BhuffError>().unwrap();
+        }
+        match err.kind() {
+            io::ErrorKind::UnexpectedEof => BhuffError::Truncated,
+            _ => BhuffError::Io(err),
+        }
+    }
+}
+
+impl From<BhuffError> for io::Error {
+    fn from(err: BhuffError) -> Self {
+        let kind = match err {
+            BhuffError::Io(err)   => return err,
+            BhuffError::Truncated => io::ErrorKind::UnexpectedEof,
+            BhuffError::Usage(_)  => io::ErrorKind::InvalidInput,
+            _ => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, err)
+    }
+}
@@ -0,0 +1,210 @@
+/* The .bhf container:
+ *
+ *   magic    4 bytes, "BHF\x1a"
+ *   version  1 byte, 1 to 4
+ *   flags    1 byte, reserved (must be zero)
+ *   method   1 byte, identifies the entropy coder:
+ *              0 = the built-in tree
+ *              1 = a tree for every block, stored in front of its data
+ *              2 = the tree of a model file (see model.rs)
+ *              3 = up to 6 trees for every block, switched every 50 symbols
+ *                  (see tables.rs)
+ *              4 = adaptive range coding, order 0 (see range.rs)
+ *              5 = adaptive range coding, order 1
+ *              6 = one-pass adaptive Huffman coding of the bytes themselves,
+ *                  without a transform (see dynamic.rs)
+ *   stages   version 4: 1 byte, the number of stages the blocks go through
+ *            before the zero-run coding (at most 8), and 1 byte for each,
+ *            in the order they are applied (see pipeline.rs):
+ *              0 = the Burrows-Wheeler transform
+ *              1 = the bijective BWT, which needs no start position
+ *              2 = move-to-front, 3 = MTF-1, 4 = MTF-2, 5 = sticky MTF,
+ *              6 = weighted frequency count, 7 = timestamp,
+ *              8 = inversion frequencies, 9 = distance coding (see rank.rs)
+ *              10 = bzip2's run-length coding, 11 = delta, 12 = x86 filter
+ *            older versions always have two stages, a BWT and a rank transform:
+ *   transform 1 byte, versions 2 and 3 (version 1 always uses 0), the
+ *            BWT as above
+ *   rank     1 byte, version 3 (older versions use 0), stage 2 + rank
+ *   blocks   varint, the maximum size of a block (at most 900000)
+ *   model    4 bytes, hash of the model file (method 2 only)
+ *
+ * followed by a sequence of independently transformed blocks:
+ *
+ *   length   varint, size of the original data in this block
+ *   crc      4 bytes, CRC-32 of the original data in this block
+ *   params   varints, the parameters of the stages: the start position of
+ *            the Burrows-Wheeler transform (none for method 6)
+ *   symbols  varint, number of coded symbols (not for method 6)
+ *   size     varint, number of bytes that follow
+ *   lengths  257 x 5 bits, canonical Huffman code length of every symbol (method 1)
+ *   tables   trees and their selectors (method 3)
+ *   ...      Huffman coded data, padded with zero bits to a byte boundary,
+ *            or the output of the range coder (methods 4 and 5);
+ *            with method 6, the tree carries over from one block to the next
+ *
+ * and terminated by a block with length zero, which is followed by
+ *
+ *   crc      4 bytes, the combined CRC of all blocks
+ *
+ * All integers are stored as (unsigned) LEB128 varints, or as big-endian
+ * CRCs, so the format does not depend on the word size or byte order of the
+ * machine.
+ */
+
+use std::io::Read;
+use crate::error::{BhuffError,Result};
+use crate::block::MAX_BLOCK_SIZE;
+use crate::pipeline::{Pipeline,MAX_STAGES};
+
+pub const MAGIC: [u8; 4] = *b"BHF\x1a";
+pub const VERSION: u8 = 4;
+
+pub const METHOD_BUILTIN: u8 = 0;
+pub const METHOD_ADAPTIVE: u8 = 1;
+pub const METHOD_MODEL: u8 = 2;
+pub const METHOD_TABLES: u8 = 3;
+pub const METHOD_RANGE0: u8 = 4;
+pub const METHOD_RANGE1: u8 = 5;
+pub const METHOD_DYNAMIC: u8 = 6;
+
+pub const STAGE_BWT: u8 = 0;
+pub const STAGE_BWTS: u8 = 1;
+pub const STAGE_MTF: u8 = 2;
+pub const STAGE_LAST_RANK: u8 = STAGE_MTF + RANK_DC;
+pub const STAGE_RLE: u8 = 10;
+pub const STAGE_DELTA: u8 = 11;
+pub const STAGE_X86: u8 = 12;
+
+pub const RANK_MTF: u8 = 0;
+pub const RANK_MTF1: u8 = 1;
+pub const RANK_MTF2: u8 = 2;
+pub const RANK_STICKY: u8 = 3;
+pub const RANK_WFC: u8 = 4;
+pub const RANK_TIMESTAMP: u8 = 5;
+pub const RANK_IF: u8 = 6;
+pub const RANK_DC: u8 = 7;
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct Header {
+    pub flags: u8,
+    pub method: u8,
+    pub pipeline: Pipeline,
+    pub block_size: usize,
+    pub model: u32,
+}
+
+pub fn put_varint(out: &mut Vec<u8>, mut n: usize) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8)
+}
+
+pub fn get_byte(input: &mut impl Read) -> Result<u8> {
+    let mut byte = [0];
+    input.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+pub fn get_varint(input: &mut impl Read) -> Result<usize> {
+    let mut n: usize = 0;
+    for shift in (0..usize::BITS).step_by(7) {
+        let byte = get_byte(input)?;
+        let bits = (byte & 0x7F) as usize;
+        if bits << shift >> shift != bits {
+            break
+        }
+        n |= bits << shift;
+        if byte & 0x80 == 0 {
+            return Ok(n)
+        }
+    }
+    Err(BhuffError::Corrupt(None, "integer out of range"))
+}
+
+pub fn get_u32(input: &mut impl Read) -> Result<u32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+impl Header {
+    // the model hash is only stored for METHOD_MODEL
+    pub fn new(method: u8, pipeline: Pipeline, block_size: usize, model: u32) -> Self {
+        Header { flags: 0, method, pipeline, block_size, model }
+    }
+
+    // streams that need nothing new are written as the oldest version that can hold them
+    pub fn to_bytes(self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        match *self.pipeline.stages() {
+            [transform @ (STAGE_BWT | STAGE_BWTS), rank @ STAGE_MTF..=STAGE_LAST_RANK] => {
+                let rank = rank - STAGE_MTF;
+                let version = if rank != RANK_MTF { 3 } else if transform != STAGE_BWT { 2 } else { 1 };
+                out.extend([version, self.flags, self.method]);
+                out.extend([transform, rank].into_iter().take(version as usize - 1));
+            }
+            ref stages => {
+                out.extend([VERSION, self.flags, self.method, stages.len() as u8]);
+                out.extend(stages);
+            }
+        }
+        put_varint(&mut out, self.block_size);
+        if self.method == METHOD_MODEL {
+            out.extend(self.model.to_be_bytes());
+        }
+        out
+    }
+
+    pub fn read(input: &mut impl Read) -> Result<Self> {
+        let mut magic = [0; 4];
+        input.read_exact(&mut magic).map_err(|err| match err.into() {
+            BhuffError::Truncated => BhuffError::BadMagic,
+            err => err,
+        })?;
+        if magic != MAGIC {
+            return Err(BhuffError::BadMagic)
+        }
+        let version = get_byte(input)?;
+        if !(1..=VERSION).contains(&version) {
+            return Err(BhuffError::UnsupportedVersion(version))
+        }
+        let flags = get_byte(input)?;
+        if flags != 0 {
+            return Err(BhuffError::UnsupportedFlags(flags))
+        }
+        let method = get_byte(input)?;
+        if method > METHOD_DYNAMIC {
+            return Err(BhuffError::UnsupportedMethod(method))
+        }
+        let stages = match version {
+            4 => {
+                let count = get_byte(input)? as usize;
+                if count > MAX_STAGES {
+                    return Err(BhuffError::Corrupt(None, "too many stages"));
+                }
+                (0..count).map(|_| get_byte(input)).collect::<Result<Vec<u8>>>()?
+            }
+            _ => {
+                let transform = if version > 1 { get_byte(input)? } else { STAGE_BWT };
+                let rank = if version > 2 { get_byte(input)? } else { RANK_MTF };
+                if transform > STAGE_BWTS || rank > RANK_DC {
+                    return Err(BhuffError::Corrupt(None, "unknown transform"));
+                }
+                vec![transform, STAGE_MTF + rank]
+            }
+        };
+        if stages.iter().any(|&stage| stage > STAGE_X86) {
+            return Err(BhuffError::Corrupt(None, "unknown transform"));
+        }
+        let pipeline = Pipeline::new(&stages);
+        let block_size = get_varint(input)?;
+        if block_size == 0 || block_size > MAX_BLOCK_SIZE {
+            return Err(BhuffError::Corrupt(None, "invalid block size"))
+        }
+        let model = if method == METHOD_MODEL { get_u32(input)? } else { 0 };
+        Ok(Header { flags, method, pipeline, block_size, model })
+    }
+}
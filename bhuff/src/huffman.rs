@@ -0,0 +1,348 @@
+/* Huffman trees and canonical codes */
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::iter;
+
+use crate::transform::ALPHABET;
+
+//frequencies :: (Ord a) => [a] -> [(a,Int)]
+//frequencies = map (\x->(head x, length x)) . group . sort
+
+pub type FreqTable<T> = Vec<(T,usize)>;
+
+pub fn frequency_table<T, Iter>(stream: Iter) -> Option<FreqTable<T>>
+  where Iter: Iterator<Item=T>,
+        T: Ord + Copy
+{
+    let text = { let mut tmp: Vec<T> = stream.collect(); tmp.sort_unstable(); tmp };
+
+    let mut vec  = Vec::new();
+    let mut last = (*text.first()?, 1);
+
+    for &c in text.iter().skip(1) {
+        if last.0 == c {
+            last.1 += 1
+        } else {
+            vec.push(last);
+            last = (c, 1)
+        }
+    };
+    vec.push(last);
+    Some(vec)
+}
+
+// data Btree a = Tip a | Bin (Btree a) (Btree a)
+#[derive(Debug,Clone,Copy)]
+pub enum BTree<'a,T> {
+    Tip(T),
+    Bin(&'a BTree<'a,T>, &'a BTree<'a,T>)
+}
+
+// huffman :: [(a,Int)] -> Btree a
+// huffman = go . sortOn fst . map (\(k,n)->(n, Tip k))
+//   where go (code1:code2:rest) = go $ insertBy (\x y->fst x `compare` fst y) (code1<+>code2) rest
+//         go [(_,tree)] = tree
+//         go [] = error "huffman: no data to encode"
+// 
+//         (n1,t1) <+> (n2,t2) = (n1+n2, Bin t1 t2)
+
+use crate::plumbing::{LocalPlumber,Alloc};
+
+pub fn huffman_tree<'b,F,T>(freq: &FreqTable<T>, mut alloc: F) -> Option<BTree<'b, T>>
+  where F: Alloc<'b, BTree<'b,T>>,
+        T: Copy
+{
+    type Pair<'b,T> = (usize, BTree<'b, T>);
+    let mut queue: Vec<Pair<T>> = freq.iter().map(|&(chr,n)| (n, BTree::Tip(chr))).collect();
+
+    queue.sort_unstable_by_key(|x|x.0);
+
+    while queue.len() > 1 {
+        let ((n1,t1),(n2,t2)) = { let mut items = queue.drain(0..=1); (items.next().unwrap(), items.next().unwrap()) };
+        let u1 = alloc.obtain(t1);
+        let u2 = alloc.obtain(t2);
+        let el  = (n1+n2, BTree::Bin(u1,u2));
+        let pos = queue.binary_search_by_key(&el.0, |x|x.0).unwrap_or_else(|x|x);
+        queue.insert(pos, el);
+    }
+
+    queue.pop().map(|x| x.1)
+}
+
+//codes :: Btree a -> [(a,[Bit])]
+//codes (Tip x)   = [ (x, []) ]
+//codes (Bin l r) = [ (xl,O:cl) | (xl,cl) <- codes l ] ++
+//                  [ (xr,I:cr) | (xr,cr) <- codes r ]
+
+use crate::bitstring::{self,Bits};
+
+pub type BitString = bitstring::RealBits;
+
+pub fn codes<T: Eq + Hash + Copy> (huftree: &BTree<T>) -> HashMap<T, BitString> {
+    fn walk<T: Eq + Hash + Copy>(map: &mut HashMap<T,BitString>, node: &BTree<T>, code: BitString) {
+        match node {
+               BTree::Tip(c)     => { map.insert(*c, code); },
+               BTree::Bin(t1,t2) => { walk(map, t1, code.append(false));
+                                      walk(map, t2, code.append(true)); }
+        }
+    }
+
+    let mut map = HashMap::new();
+    walk(&mut map, huftree, Bits::new());
+    map
+}
+
+/* Canonical Huffman codes: only the code length of every symbol needs to be
+ * stored; codes are then handed out in order of length, and by symbol value
+ * for codes of equal length.
+ * https://en.wikipedia.org/wiki/Canonical_Huffman_code
+ */
+
+/* Code lengths are limited to MAX_CODE_LEN bits, so that a code fits in a
+ * BitString and decoders can use fixed size tables. Should the Huffman tree
+ * be too deep, the lengths are recomputed using the package-merge algorithm:
+ * L.L. Larmore, D.S. Hirschberg: "A fast algorithm for optimal length-limited Huffman codes"
+ * https://doi.org/10.1145/79147.79150
+ */
+
+pub const MAX_CODE_LEN: u8 = 20;
+pub const LENGTH_BITS: u8 = 5;
+
+// N is the size of the alphabet, ALPHABET except for bzip2
+pub fn code_lengths<const N: usize>(huftree: &BTree<u16>) -> Option<[u8; N]> {
+    fn walk(lengths: &mut [u8], node: &BTree<u16>, depth: u8) -> Option<()> {
+        match node {
+            BTree::Tip(c)     => { lengths[*c as usize] = depth; },
+            BTree::Bin(t1,t2) => { let depth = Some(depth+1).filter(|&d| d <= MAX_CODE_LEN)?;
+                                   walk(lengths, t1, depth)?;
+                                   walk(lengths, t2, depth)?; }
+        }
+        Some(())
+    }
+
+    let mut lengths = [0; N];
+    walk(&mut lengths, huftree, 0)?;
+    Some(lengths)
+}
+
+pub fn package_merge<const N: usize>(freq: &FreqTable<u16>, max_len: u8) -> [u8; N] {
+    let n = freq.len();
+    // every item records how many times it contains each of the n symbols
+    let leaves: Vec<(usize, Vec<u8>)> = freq.iter().enumerate().map(|(i, &(_, weight))| {
+        let mut count = vec![0; n];
+        count[i] = 1;
+        (weight, count)
+    }).collect();
+
+    let mut list = leaves.clone();
+    list.sort_by_key(|item| item.0);
+    for _ in 1..max_len {
+        let packages = list.chunks_exact(2).map(|pair| {
+            let count = pair[0].1.iter().zip(&pair[1].1).map(|(a,b)| a+b).collect();
+            (pair[0].0 + pair[1].0, count)
+        });
+        list = leaves.iter().cloned().chain(packages).collect();
+        list.sort_by_key(|item| item.0);
+    }
+
+    let mut lengths = [0; N];
+    for (_, count) in &list[..2*n-2] {
+        for (&(sym, _), c) in freq.iter().zip(count) {
+            lengths[sym as usize] += c;
+        }
+    }
+    lengths
+}
+
+pub fn limited_code_lengths<const N: usize>(freq: &FreqTable<u16>) -> [u8; N] {
+    let prealloc = &mut vec![BTree::Tip(0); 2*N-2];
+    let tree = huffman_tree(freq, LocalPlumber(prealloc)).unwrap();
+    code_lengths(&tree).unwrap_or_else(|| package_merge(freq, MAX_CODE_LEN))
+}
+
+pub fn canonical_order(lengths: &[u8]) -> Vec<(u8,u16)> {
+    let mut syms: Vec<(u8,u16)> = lengths.iter().cloned().zip(0..).filter(|&(len,_)| len > 0).collect();
+    syms.sort_unstable();
+    syms
+}
+
+pub fn canonical_codes(lengths: &[u8]) -> HashMap<u16, BitString> {
+    let mut code = 0;
+    let mut prev = 0;
+    canonical_order(lengths).into_iter().map(|(len, sym)| {
+        code <<= len - prev;
+        prev = len;
+        code += 1;
+        (sym, BitString::with_len(code-1, len))
+    }).collect()
+}
+
+// the leaves of a canonical tree appear in canonical order from left to right
+pub fn canonical_tree<'b,F>(lengths: &[u8], mut alloc: F) -> Option<BTree<'b, u16>>
+  where F: Alloc<'b, BTree<'b,u16>>
+{
+    fn build<'b,F>(syms: &mut iter::Peekable<impl Iterator<Item=(u8,u16)>>, depth: u8, alloc: &mut F) -> Option<BTree<'b, u16>>
+      where F: Alloc<'b, BTree<'b,u16>>
+    {
+        let &(len, sym) = syms.peek()?;
+        if len == depth {
+            syms.next();
+            Some(BTree::Tip(sym))
+        } else {
+            let t1 = build(syms, depth+1, alloc)?;
+            let t2 = build(syms, depth+1, alloc)?;
+            Some(BTree::Bin(alloc.obtain(t1), alloc.obtain(t2)))
+        }
+    }
+
+    let mut syms = canonical_order(lengths).into_iter().peekable();
+    let tree = build(&mut syms, 0, &mut alloc)?;
+    // all symbols must be used up, and the root cannot be a leaf
+    syms.peek().is_none().then_some(tree).filter(|tree| matches!(tree, BTree::Bin(..)))
+}
+
+pub fn bits_to_bytes(stream: impl Iterator<Item=bool>) -> impl Iterator<Item=u8> {
+    iter::once(0)
+    .chain(
+        stream.chain(iter::repeat_n(false,7))
+              .scan(0, |acc, b| { *acc = (*acc << 1) + (b as u8); Some(*acc) })
+    )
+    .step_by(8)
+    .skip(1)
+}
+
+pub fn get_bits(bits: &mut impl Iterator<Item=bool>, count: u8) -> Option<u8> {
+    (0..count).try_fold(0, |acc, _| Some(acc << 1 | bits.next()? as u8))
+}
+
+// a lone symbol still needs a one-bit code, so it gets a partner in the alphabet
+pub fn pad_lone_symbol(ftab: &mut FreqTable<u16>, freq: usize) {
+    if let [(sym, _)] = ftab[..] {
+        ftab.push((if sym == 0 { 1 } else { sym - 1 }, freq));
+    }
+}
+
+pub fn block_code_lengths(block: &[u16]) -> [u8; ALPHABET] {
+    let mut ftab = frequency_table(block.iter().cloned()).unwrap();
+    pad_lone_symbol(&mut ftab, 0);
+    limited_code_lengths(&ftab)
+}
+
+pub fn huffman_encode(static_codes: Option<&HashMap<u16,BitString>>, block: &[u16]) -> Vec<u8> {
+    let mut table = Vec::new();
+    let block_codes;
+    let cmap = match static_codes {
+        Some(cmap) => cmap,
+        None => {
+            let lengths = block_code_lengths(block);
+            table.extend(lengths.map(|len| BitString::with_len(len as usize, LENGTH_BITS)));
+            block_codes = canonical_codes(&lengths);
+            &block_codes
+        }
+    };
+    let compressed_bits = table.into_iter().chain(block.iter().map(|x| *cmap.get(x).unwrap())).flatten();
+    bits_to_bytes(compressed_bits).collect()
+}
+
+pub fn read_code_lengths(bits: &mut impl Iterator<Item=bool>) -> Option<Vec<u8>> {
+    let lengths = (0..ALPHABET)
+        .map(|_| get_bits(bits, LENGTH_BITS).filter(|&len| len <= MAX_CODE_LEN))
+        .collect::<Option<Vec<u8>>>()?;
+    let kraft_sum: u32 = lengths.iter().filter(|&&len| len > 0).map(|&len| 1 << (MAX_CODE_LEN - len)).sum();
+    (kraft_sum == 1 << MAX_CODE_LEN).then_some(lengths)
+}
+
+pub static HUFFTREE : &BTree<u16> = { 
+    use BTree::{Tip,Bin}; 
+    include!("hufftree.in") 
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration,Instant};
+    use crate::bitstring::BitReader;
+    use crate::pipeline::Pipeline;
+    use crate::lookup::LookupTable;
+    use crate::block::BLOCK_UNIT;
+
+    fn round_trip(block: &[u16]) -> Option<Vec<u16>> {
+        let payload = huffman_encode(None, block);
+        let mut bits = BitReader::new(&payload);
+        let table = LookupTable::new(&canonical_codes(&read_code_lengths(&mut bits)?));
+        (0..block.len()).map(|_| table.decode(&mut bits)).collect()
+    }
+
+    // bwt | mtf makes the top symbol 256 of a single 0xff
+    #[test]
+    fn lone_symbol() {
+        let data = [0xff];
+        let (params, symbols) = Pipeline::default().transform(data.to_vec());
+        assert_eq!(symbols, [256]);
+        let decoded = round_trip(&symbols).unwrap();
+        let output: Vec<u8> = Pipeline::default().untransform(&params, decoded.into_iter(), data.len()).unwrap().collect();
+        assert_eq!(output, data);
+        for block in [vec![0; 10], vec![1; 10], vec![256; 10]] {
+            assert_eq!(round_trip(&block).unwrap(), block);
+        }
+    }
+
+    // the reference decoder, walking the tree bit by bit
+    fn decode_block(root: &BTree<u16>, len: usize, bits: &mut impl Iterator<Item=bool>) -> Option<Vec<u16>> {
+        let mut out = Vec::with_capacity(len);
+        let mut node = root;
+        while out.len() < len {
+            node = match node {
+                BTree::Bin(t1,t2) => if !bits.next()? { t1 } else { t2 },
+                BTree::Tip(_)     => unreachable!(),
+            };
+            if let BTree::Tip(sym) = node {
+                out.push(*sym);
+                node = root;
+            }
+        }
+        Some(out)
+    }
+
+    /* compares the reference tree walk with the table driven decoder, on the file
+     * named by BHUFF_BENCH or else the sources of the crate:
+     *   BHUFF_BENCH=file cargo test --release bench_decoders -- --ignored --nocapture */
+    #[test]
+    #[ignore]
+    fn bench_decoders() {
+        let data: Vec<u8> = match std::env::var_os("BHUFF_BENCH") {
+            Some(name) => std::fs::read(name).unwrap(),
+            None => std::fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/src")).unwrap()
+                .flat_map(|entry| std::fs::read(entry.unwrap().path()).unwrap()).collect(),
+        };
+        let blocks: Vec<(usize, Vec<u8>)> = data.chunks(9 * BLOCK_UNIT).map(|chunk| {
+            let (_, block) = Pipeline::default().transform(chunk.to_vec());
+            (block.len(), huffman_encode(None, &block))
+        }).collect();
+
+        fn timed<R>(work: impl FnOnce() -> R) -> (Duration, R) {
+            let start = Instant::now();
+            let result = work();
+            (start.elapsed(), result)
+        }
+
+        let (walk_time, walk_output) = timed(|| blocks.iter().map(|(symbols, payload)| {
+            let mut bits = BitReader::new(payload);
+            let prealloc = &mut [BTree::Tip(0); 2*ALPHABET-2];
+            let tree = canonical_tree(&read_code_lengths(&mut bits)?, LocalPlumber(prealloc))?;
+            decode_block(&tree, *symbols, &mut bits)
+        }).collect::<Option<Vec<_>>>());
+        let (table_time, table_output) = timed(|| blocks.iter().map(|(symbols, payload)| {
+            let mut bits = BitReader::new(payload);
+            let table = LookupTable::new(&canonical_codes(&read_code_lengths(&mut bits)?));
+            (0..*symbols).map(|_| table.decode(&mut bits)).collect::<Option<Vec<u16>>>()
+        }).collect::<Option<Vec<_>>>());
+        assert!(walk_output.is_some() && walk_output == table_output, "decoders disagree");
+
+        let mb = data.len() as f64 / 1e6;
+        println!("tree walk:    {walk_time:.2?} ({:.1} MB/s)", mb / walk_time.as_secs_f64());
+        println!("lookup table: {table_time:.2?} ({:.1} MB/s)", mb / table_time.as_secs_f64());
+        println!("speedup:      {:.2}x", walk_time.as_secs_f64() / table_time.as_secs_f64());
+    }
+}
@@ -0,0 +1,60 @@
+/* bhuff: block sorting compression with Huffman coding
+ *
+ * Data is split into blocks that are Burrows-Wheeler transformed, move-to-front
+ * coded, and Huffman coded; see header.rs for the file format, and
+ * pipeline.rs for other transforms.
+ *
+ *   let packed = bhuff::compress_to_vec(data);
+ *   assert_eq!(bhuff::decompress_to_vec(&packed)?, data);
+ *
+ * For streaming use, BhuffEncoder wraps a Write and BhuffDecoder a Read;
+ * Bz2Encoder and Bz2Decoder do the same for the bzip2 format.
+ *
+ * The decoder is meant to be safe on untrusted input: every field is checked
+ * before it is used, so invalid data results in an error rather than a panic,
+ * and the memory it uses is bounded (see BhuffDecoder::memory_limit).
+ */
+
+use std::io::{Read,Write};
+
+mod plumbing;
+mod bitstring;
+mod suffix;
+mod transform;
+mod rank;
+mod pipeline;
+mod stage;
+mod huffman;
+mod tables;
+mod range;
+mod dynamic;
+mod bzip2;
+mod lookup;
+mod header;
+mod crc;
+mod block;
+mod model;
+mod error;
+mod encoder;
+mod decoder;
+
+pub use encoder::BhuffEncoder;
+pub use decoder::{BhuffDecoder,Summary,list};
+pub use bzip2::{Bz2Encoder,Bz2Decoder};
+pub use rank::RANKINGS;
+pub use pipeline::{Pipeline,STAGE_NAMES};
+pub use stage::{STAGES,run_stage};
+pub use error::{BhuffError,Result};
+pub use model::{Model,Trainer};
+
+pub fn compress_to_vec(data: &[u8]) -> Vec<u8> {
+    let mut encoder = BhuffEncoder::new(Vec::new());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+pub fn decompress_to_vec(data: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    BhuffDecoder::new(data).read_to_end(&mut out)?;
+    Ok(out)
+}
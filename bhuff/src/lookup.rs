@@ -0,0 +1,74 @@
+/* Table driven Huffman decoding
+ *
+ * The next FAST_BITS bits of the input index a table that either gives the
+ * decoded symbol and the length of its code, or points to a second level
+ * table for the longer codes sharing that prefix.
+ */
+
+use std::collections::HashMap;
+use crate::bitstring::{BitReader,RealBits};
+
+const FAST_BITS: u32 = 10;
+
+#[derive(Debug,Clone,Copy)]
+enum Entry {
+    Invalid,
+    Sym(u16, u8),
+    Sub(u32, u8),
+}
+
+pub struct LookupTable {
+    table: Vec<Entry>,
+}
+
+impl LookupTable {
+    pub fn new(codes: &HashMap<u16, RealBits>) -> Self {
+        let codes: Vec<(u16, u32, u32)> = codes.iter().map(|(&sym, &code)| {
+            let len = code.count() as u32;
+            (sym, code.fold(0, |acc, b| acc << 1 | b as u32), len)
+        }).collect();
+
+        let mut table = vec![Entry::Invalid; 1 << FAST_BITS];
+        let mut sub_bits = vec![0; 1 << FAST_BITS];
+        for &(_, code, len) in codes.iter().filter(|x| x.2 > FAST_BITS) {
+            let prefix = (code >> (len - FAST_BITS)) as usize;
+            sub_bits[prefix] = sub_bits[prefix].max(len - FAST_BITS);
+        }
+        for (prefix, &bits) in sub_bits.iter().enumerate().filter(|x| *x.1 > 0) {
+            table[prefix] = Entry::Sub(table.len() as u32, bits as u8);
+            table.extend(std::iter::repeat_n(Entry::Invalid, 1 << bits));
+        }
+
+        for (sym, code, len) in codes {
+            // the part of the code that indexes this table, and the size of the table
+            let (start, index, used, bits) = if len <= FAST_BITS {
+                (0, code, len, FAST_BITS)
+            } else {
+                let Entry::Sub(start, bits) = table[(code >> (len - FAST_BITS)) as usize] else { unreachable!() };
+                (start as usize, code & ((1 << (len - FAST_BITS)) - 1), len - FAST_BITS, bits as u32)
+            };
+            let fill = bits - used;
+            for i in 0..1 << fill {
+                table[start + ((index << fill) | i) as usize] = Entry::Sym(sym, len as u8);
+            }
+        }
+
+        LookupTable { table }
+    }
+
+    pub fn decode(&self, input: &mut BitReader) -> Option<u16> {
+        let (sym, len) = match self.table[input.peek(FAST_BITS) as usize] {
+            Entry::Sym(sym, len) => (sym, len),
+            Entry::Sub(start, bits) => {
+                let index = input.peek(FAST_BITS + bits as u32) & ((1 << bits) - 1);
+                match self.table[start as usize + index as usize] {
+                    Entry::Sym(sym, len) => (sym, len),
+                    _ => return None,
+                }
+            }
+            Entry::Invalid => return None,
+        };
+        input.consume(len as u32);
+        (!input.overrun()).then_some(sym)
+    }
+}
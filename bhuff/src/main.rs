@@ -1,189 +1,488 @@
 use std::io;
-use std::io::{Error,Read,Write};
-use std::collections::HashMap;
-use std::hash::Hash;
-use std::iter;
+use std::io::{BufRead,IsTerminal,Read,Write};
+use std::fs;
 use std::env;
+use std::process::ExitCode;
+use std::thread;
+
+use bhuff::{BhuffEncoder,BhuffDecoder,Bz2Encoder,Bz2Decoder,BhuffError,Model,Trainer,Result,Summary,Pipeline,RANKINGS,STAGES,STAGE_NAMES,list,run_stage};
+
+/* Command line interface, modelled after gzip */
+
+const SUFFIX: &str = ".bhf";
+const BZ2_SUFFIX: &str = ".bz2";
+
+const USAGE: &str = "\
+usage: bhuff [-cdfkltv19] [-T threads] [-M megabytes] [-static | -m model | -range | -dynamic]
+             [-bwts] [--rank=name | --pipeline stages] [--format=bhf|bz2] [file ...]
+       bhuff analyze [-19] [-T threads] [-static | -m model | -range] [-bwts | --pipeline stages] [file ...]
+       bhuff bwt [-19] | unbwt | mtf | unmtf | huff | unhuff [file ...]
+       bhuff -train [-o model] [file | dir ...]
+
+  -c      write to standard output, keep the input files
+  -d      decompress
+  -f      overwrite existing files, write compressed data to a terminal
+  -k      keep the input files
+  -l      list information about compressed files
+  -t      test the integrity of compressed files
+  -v      report compression ratios
+  -1..-9  block size of 100k..900k (default -9)
+  -T N    process N blocks in parallel (0 = one per core)
+  -M N    use at most N MiB of memory to decompress (default 256)
+  -static use the built-in Huffman tree for every block
+  -m FILE use the Huffman tree of a model file for every block
+  -range  use an adaptive range coder instead of Huffman coding
+  -range0 the same, without using the previous symbol as context
+  -dynamic code bytes in a single pass as they arrive, with an adaptive tree
+  -bwts   use the bijective BWT, which needs no start position per block
+  --rank=NAME the stage after the BWT: mtf (default), mtf1, mtf2, sticky (MTF
+          variants), wfc (weighted frequency count), ts (timestamp), if
+          (inversion frequencies) or dc (distance coding)
+  --pipeline STAGES the transforms in front of the entropy coder, separated by
+          commas (default bwt,mtf): bwt, bwts, any --rank, rle (bzip2's run-
+          length coding), delta (byte differences) and x86 (call addresses)
+  --format=bz2 write .bz2 files that bzip2 can read (-d detects them itself)
+  -train  print a Huffman tree trained on the files, and all files below dirs
+  -o FILE with -train, write a model file instead
+  analyze compress the files with every --rank, and report which one wins
+  bwt ... run a single stage of the compressor, or its inverse, as a filter
+          from the files to standard output (see src/stage.rs for the formats)
+
+Without files, or when a file is -, standard input is used.";
+
+#[derive(Clone,Copy,PartialEq,Eq)]
+enum Mode { Compress, Decompress, Test, List, Train, Analyze, Stage }
+
+struct Options {
+    mode: Mode,
+    stage: &'static str,
+    stdout: bool,
+    force: bool,
+    keep: bool,
+    verbose: bool,
+    level: usize,
+    threads: usize,
+    memory: usize,
+    static_tree: bool,
+    range_order: Option<usize>,
+    dynamic: bool,
+    bijective: bool,
+    rank: Option<&'static str>,
+    pipeline: Option<Pipeline>,
+    bz2: bool,
+    model_file: Option<String>,
+    model: Option<Model>,
+    train_output: Option<String>,
+}
 
-//frequencies :: (Ord a) => [a] -> [(a,Int)]
-//frequencies = map (\x->(head x, length x)) . group . sort
-
-type FreqTable<T> = Vec<(T,usize)>;
-
-fn frequency_table<T, Iter>(stream: Iter) -> Option<FreqTable<T>>
-  where Iter: Iterator<Item=T>,
-        T: Ord + Copy
-{
-    let text = { let mut tmp: Vec<T> = stream.collect(); tmp.sort_unstable(); tmp };
-
-    let mut vec  = Vec::new();
-    let mut last = (*text.first()?, 1);
+fn usage(msg: impl Into<String>) -> BhuffError {
+    BhuffError::Usage(msg.into())
+}
 
-    for &c in text.iter().skip(1) {
-        if last.0 == c {
-            last.1 += 1
-        } else {
-            vec.push(last);
-            last = (c, 1)
-        }
+fn parse_args(args: impl IntoIterator<Item=String>) -> Result<(Options, Vec<String>)> {
+    let mut opts = Options {
+        mode: Mode::Compress, stage: "", stdout: false, force: false, keep: false, verbose: false,
+        level: 9, threads: 1, memory: 256 << 20, static_tree: false, range_order: None,
+        dynamic: false, bijective: false, rank: None, pipeline: None, bz2: false, model_file: None, model: None, train_output: None,
     };
-    vec.push(last);
-    Some(vec)
+    let mut files = Vec::new();
+    let mut args = args.into_iter().peekable();
+    let mut only_files = false;
+    // analyze and the stages are commands; a file of such a name can be given as ./name
+    if args.next_if(|arg| arg == "analyze").is_some() {
+        opts.mode = Mode::Analyze;
+    } else if let Some(arg) = args.next_if(|arg| STAGES.contains(&arg.as_str())) {
+        opts.mode = Mode::Stage;
+        opts.stage = STAGES.into_iter().find(|&stage| stage == arg).unwrap();
+    }
+    while let Some(arg) = args.next() {
+        if only_files || arg == "-" || !arg.starts_with('-') {
+            files.push(arg);
+            continue;
+        }
+        match arg.as_str() {
+            "--"      => only_files = true,
+            "-train"  => opts.mode = Mode::Train,
+            "-static" => opts.static_tree = true,
+            "-range"  => opts.range_order = Some(1),
+            "-range0" => opts.range_order = Some(0),
+            "-dynamic" => opts.dynamic = true,
+            "-bwts" => opts.bijective = true,
+            _ if arg.starts_with("--rank=") => {
+                let Some(&(name, _)) = RANKINGS.iter().find(|&&(name, _)| arg[7..] == *name) else {
+                    return Err(usage(format!("unknown rank transform {}\n{USAGE}", &arg[7..])));
+                };
+                opts.rank = Some(name);
+            }
+            _ if arg == "--pipeline" || arg.starts_with("--pipeline=") => {
+                let Some(stages) = (if arg.len() > 10 { Some(arg[11..].to_string()) } else { args.next() }) else {
+                    return Err(usage(format!("--pipeline requires an argument\n{USAGE}")));
+                };
+                let pipeline = Pipeline::parse(&stages).map_err(|err| {
+                    let names: Vec<&str> = STAGE_NAMES.iter().map(|&(name, _)| name).collect();
+                    usage(format!("invalid pipeline {stages}: {err}; up to 8 of {}", names.join(", ")))
+                })?;
+                opts.pipeline = Some(pipeline);
+            }
+            "--format=bhf" => opts.bz2 = false,
+            "--format=bz2" => opts.bz2 = true,
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                std::process::exit(0);
+            }
+            _ => for (i, flag) in arg.char_indices().skip(1) {
+                match flag {
+                    'c' => opts.stdout = true,
+                    'd' => opts.mode = Mode::Decompress,
+                    'f' => opts.force = true,
+                    'k' => opts.keep = true,
+                    'l' => opts.mode = Mode::List,
+                    't' => opts.mode = Mode::Test,
+                    'v' => opts.verbose = true,
+                    '1'..='9' => opts.level = flag as usize - '0' as usize,
+                    'T' | 'M' | 'm' | 'o' => {
+                        // the value may be attached (-T4) or a separate argument (-T 4)
+                        let rest = &arg[i+1..];
+                        let Some(value) = (if rest.is_empty() { args.next() } else { Some(rest.to_string()) }) else {
+                            return Err(usage(format!("-{flag} requires an argument\n{USAGE}")));
+                        };
+                        let number = || value.parse::<usize>().map_err(|_| usage(format!("-{flag} requires a number\n{USAGE}")));
+                        match flag {
+                            'T' => opts.threads = match number()? {
+                                0 => thread::available_parallelism()?.get(),
+                                n => n,
+                            },
+                            'M' => opts.memory = number()?.saturating_mul(1 << 20),
+                            'm' => opts.model_file = Some(value),
+                            _   => opts.train_output = Some(value),
+                        }
+                        break;
+                    }
+                    _ => return Err(usage(format!("unknown option -{flag}\n{USAGE}"))),
+                }
+            }
+        }
+    }
+    if opts.static_tree as usize + opts.model_file.is_some() as usize + opts.range_order.is_some() as usize + opts.dynamic as usize > 1 {
+        return Err(usage("only one of -static, -m, -range and -dynamic can be used"));
+    }
+    if opts.bz2 && (opts.static_tree || opts.model_file.is_some() || opts.range_order.is_some() || opts.dynamic) {
+        return Err(usage("--format=bz2 cannot be combined with -static, -m, -range or -dynamic"));
+    }
+    if opts.bijective && (opts.dynamic || opts.bz2) {
+        return Err(usage("-bwts cannot be combined with -dynamic or --format=bz2"));
+    }
+    if (opts.rank.is_some() || opts.pipeline.is_some()) && (opts.dynamic || opts.bz2) {
+        return Err(usage("--rank and --pipeline cannot be combined with -dynamic or --format=bz2"));
+    }
+    if opts.pipeline.is_some() && (opts.rank.is_some() || opts.bijective) {
+        return Err(usage("--pipeline cannot be combined with -bwts or --rank"));
+    }
+    if opts.mode == Mode::Analyze && (opts.dynamic || opts.bz2) {
+        return Err(usage("analyze cannot be combined with -dynamic or --format=bz2"));
+    }
+    // analyze swaps the rank stage, so there has to be one
+    if opts.mode == Mode::Analyze && opts.pipeline.is_some_and(|pipeline| !pipeline.has_rank()) {
+        return Err(usage("analyze needs a --pipeline with a rank stage"));
+    }
+    Ok((opts, files))
 }
 
-// data Btree a = Tip a | Bin (Btree a) (Btree a)
-#[derive(Debug,Clone,Copy)]
-enum BTree<'a,T> {
-    Tip(T),
-    Bin(&'a BTree<'a,T>, &'a BTree<'a,T>)
+/* counts the bytes passing through a reader or writer */
+struct Counter<T> {
+    inner: T,
+    count: u64,
 }
 
-// huffman :: [(a,Int)] -> Btree a
-// huffman = go . sortOn fst . map (\(k,n)->(n, Tip k))
-//   where go (code1:code2:rest) = go $ insertBy (\x y->fst x `compare` fst y) (code1<+>code2) rest
-//         go [(_,tree)] = tree
-//         go [] = error "huffman: no data to encode"
-// 
-//         (n1,t1) <+> (n2,t2) = (n1+n2, Bin t1 t2)
-
-mod plumbing;
-use plumbing::{LocalPlumber,Alloc};
-
-fn huffman_tree<'b,F,T>(freq: &FreqTable<T>, mut alloc: F) -> Option<BTree<'b, T>>
-  where F: Alloc<'b, BTree<'b,T>>,
-        T: Copy
-{
-    type Pair<'b,T> = (usize, BTree<'b, T>);
-    let mut queue: Vec<Pair<T>> = freq.iter().map(|&(chr,n)| (n, BTree::Tip(chr))).collect();
-
-    queue.sort_unstable_by_key(|x|x.0);
-
-    while queue.len() > 1 {
-        let ((n1,t1),(n2,t2)) = { let mut items = queue.drain(0..=1); (items.next().unwrap(), items.next().unwrap()) };
-        let u1 = alloc.obtain(t1);
-        let u2 = alloc.obtain(t2);
-        let el  = (n1+n2, BTree::Bin(u1,u2));
-        let pos = queue.binary_search_by_key(&el.0, |x|x.0).unwrap_or_else(|x|x);
-        queue.insert(pos, el);
+impl<T> Counter<T> {
+    fn new(inner: T) -> Self {
+        Counter { inner, count: 0 }
     }
-
-    queue.pop().map(|x| x.1)
 }
 
-//codes :: Btree a -> [(a,[Bit])]
-//codes (Tip x)   = [ (x, []) ]
-//codes (Bin l r) = [ (xl,O:cl) | (xl,cl) <- codes l ] ++
-//                  [ (xr,I:cr) | (xr,cr) <- codes r ]
+impl<T: Read> Read for Counter<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+}
 
-mod bitstring;
-use bitstring::Bits;
+impl<T: BufRead> BufRead for Counter<T> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
 
-type BitString = bitstring::RealBits;
+    fn consume(&mut self, n: usize) {
+        self.count += n as u64;
+        self.inner.consume(n)
+    }
+}
 
-fn codes<T: Eq + Hash + Copy> (huftree: &BTree<T>) -> HashMap<T, BitString> {
-    fn walk<T: Eq + Hash + Copy>(map: &mut HashMap<T,BitString>, node: &BTree<T>, code: BitString) {
-        match node {
-               BTree::Tip(c)     => { map.insert(*c, code); },
-               BTree::Bin(t1,t2) => { walk(map, t1, code.append(false));
-                                      walk(map, t2, code.append(true)); }
-        }
+impl<T: Write> Write for Counter<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
     }
 
-    let mut map = HashMap::new();
-    walk(&mut map, huftree, Bits::new());
-    map
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
 }
 
-fn bits_to_bytes(stream: impl Iterator<Item=bool>) -> impl Iterator<Item=u8> {
-    iter::once(0)
-    .chain(
-        stream.chain(iter::repeat_n(false,7))
-              .scan(0, |acc, b| { *acc = (*acc << 1) + (b as u8); Some(*acc) })
-    )
-    .step_by(8)
-    .skip(1)
+fn open_input(name: &str, compressed: bool, opts: &Options) -> Result<Counter<Box<dyn BufRead>>> {
+    let input: Box<dyn BufRead> = if name == "-" {
+        if compressed && !opts.force && io::stdin().is_terminal() {
+            return Err(usage("compressed data not read from a terminal, use -f to force"));
+        }
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(io::BufReader::new(fs::File::open(name)?))
+    };
+    Ok(Counter::new(input))
 }
 
-fn bytes_to_bits(stream: impl Iterator<Item=u8>) -> impl Iterator<Item=bool> {
-    stream.flat_map(bitstring::RealBits::from_u8)
+fn open_output(name: Option<&str>, compressed: bool, opts: &Options) -> Result<Counter<Box<dyn Write>>> {
+    let output: Box<dyn Write> = match name {
+        None => {
+            if compressed && !opts.force && io::stdout().is_terminal() {
+                return Err(usage("compressed data not written to a terminal, use -f to force"));
+            }
+            Box::new(io::stdout().lock())
+        }
+        Some(name) => {
+            let file = fs::OpenOptions::new().write(true).create(true).truncate(true).create_new(!opts.force).open(name)
+                .map_err(|err| match err.kind() {
+                    io::ErrorKind::AlreadyExists => usage(format!("{name} already exists, use -f to overwrite")),
+                    _ => err.into(),
+                })?;
+            Box::new(file)
+        }
+    };
+    Ok(Counter::new(output))
 }
 
-mod transform;
-use transform::{transform,untransform};
-
-//not done: correct generation of huffman trees in case the input only has one byte
-#[allow(clippy::unit_arg)]
-fn emit_hufftree(input: impl Iterator<Item=u8>) -> Option<()> {
-    let (_, input) = transform(input);
-    let ftab = frequency_table(input.iter().cloned())?;
-
-    let prealloc = &mut [BTree::Tip(0); 510]; // 256 Tip + 255 Bin - 1 node in local variable
-    let tree = huffman_tree(&ftab, LocalPlumber(prealloc))?;
-    Some(println!("{:?}", &tree))
+fn percentage(part: u64, whole: u64) -> f64 {
+    if whole == 0 { 0.0 } else { 100.0 * part as f64 / whole as f64 }
 }
 
-fn compress(tree: &BTree<u8>, input: impl Iterator<Item=u8>) -> Option<()> {
-    let (bw_pos, input) = transform(input);
-
-    let cmap = codes(tree);
-    let compressed_bits = input.iter().flat_map(|x| *cmap.get(x).unwrap());
-
-    let mut bin_out = io::BufWriter::new(io::stdout());
-    bin_out.write_all(&input.len().to_ne_bytes()).ok()?;
-    bin_out.write_all(&bw_pos.to_ne_bytes()).ok()?;
-    for byte in bits_to_bytes(compressed_bits) {
-        bin_out.write_all(&[byte]).ok()?;
+/* runs a compression or decompression from one file into another, which
+ * replaces the original unless told otherwise */
+fn convert<F>(opts: &Options, name: &str, out_name: Option<String>, compressing: bool, work: F) -> Result<()>
+  where F: FnOnce(&mut Counter<Box<dyn BufRead>>, &mut Counter<Box<dyn Write>>) -> Result<()>
+{
+    let out_name = out_name.filter(|_| name != "-" && !opts.stdout && opts.mode != Mode::Test);
+    let mut input = open_input(name, !compressing, opts)?;
+    let mut output = match opts.mode {
+        Mode::Test => Counter::new(Box::new(io::sink()) as Box<dyn Write>),
+        _ => open_output(out_name.as_deref(), compressing, opts)?,
     };
 
-    Some(())
+    if let Err(err) = work(&mut input, &mut output) {
+        if let Some(out_name) = &out_name {
+            drop(output);
+            let _ = fs::remove_file(out_name);
+        }
+        return Err(err);
+    }
+    drop(output.inner);
+
+    let (original, compressed) = if compressing { (input.count, output.count) } else { (output.count, input.count) };
+    if let Some(out_name) = &out_name {
+        let meta = fs::metadata(name)?;
+        let file = fs::File::options().write(true).open(out_name)?;
+        file.set_permissions(meta.permissions())?;
+        file.set_modified(meta.modified()?)?;
+        if !opts.keep {
+            fs::remove_file(name)?;
+        }
+    }
+    if opts.verbose {
+        let saved = 100.0 - percentage(compressed, original);
+        match (opts.mode, &out_name) {
+            (Mode::Test, _)        => eprintln!("{name}: OK"),
+            (_, Some(out_name))    => eprintln!("{name}: {saved:5.1}% -- {} {out_name}", if opts.keep { "created" } else { "replaced with" }),
+            (_, None)              => eprintln!("{name}: {saved:5.1}%"),
+        }
+    }
+    Ok(())
 }
 
-fn get_usize(input: &mut impl Iterator<Item=u8>) -> usize {
-    let mut data = [0u8; 8];
-    for byte in &mut data {
-        *byte = input.next().unwrap()
+/* -train reads everything below the directories it is given */
+fn expand(name: &str, files: &mut Vec<String>) -> Result<()> {
+    if name == "-" || !fs::metadata(name)?.is_dir() {
+        files.push(name.to_string());
+        return Ok(());
     }
-    usize::from_ne_bytes(data)
+    let mut entries = fs::read_dir(name)?.map(|entry| entry.map(|entry| entry.path())).collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+    for path in entries {
+        // links to directories are not followed, as they could form a cycle
+        if fs::symlink_metadata(&path)?.is_symlink() && path.is_dir() {
+            continue;
+        }
+        expand(&path.to_string_lossy(), files)?;
+    }
+    Ok(())
 }
 
-fn decompress(root: &BTree<u8>, mut input: impl Iterator<Item=u8>) -> Option<()> {
-    debug_assert!(!matches!(root, BTree::Tip(_)));
+// -bwts and --rank change the default pipeline; analyze tries every rank in the given one
+fn encoder<W: Write>(opts: &Options, output: W, rank: Option<&str>) -> Result<BhuffEncoder<W>> {
+    let mut encoder = BhuffEncoder::new(output).level(opts.level).threads(opts.threads).static_tree(opts.static_tree)
+        .pipeline(opts.pipeline.unwrap_or_default());
+    if opts.bijective {
+        encoder = encoder.bijective(true);
+    }
+    if let Some(rank) = rank {
+        encoder = encoder.ranking(rank)?;
+    }
+    if let Some(model) = &opts.model {
+        encoder = encoder.model(model);
+    }
+    if let Some(order) = opts.range_order {
+        encoder = encoder.range_coder(order);
+    }
+    if opts.dynamic {
+        encoder = encoder.dynamic();
+    }
+    Ok(encoder)
+}
 
-    let inp_len = get_usize(&mut input);
-    let bw_pos  = get_usize(&mut input);
-    let mut out = Vec::<u8>::with_capacity(inp_len+1);
+/* compresses a file with every rank transform, keeping the other options */
+fn analyze(opts: &Options, name: &str) -> Result<()> {
+    let mut data = Vec::new();
+    open_input(name, false, opts)?.read_to_end(&mut data)?;
+    println!("{name}: {} bytes", data.len());
+    let mut best = None;
+    for &(rank, _) in &RANKINGS {
+        let mut encoder = encoder(opts, Vec::new(), Some(rank))?;
+        encoder.write_all(&data)?;
+        let size = encoder.finish()?.len();
+        let bits = if data.is_empty() { 0.0 } else { 8.0 * size as f64 / data.len() as f64 };
+        println!("  {rank:<8} {size:>12} {bits:6.3} bits per byte");
+        if best.is_none_or(|(_, best_size)| size < best_size) {
+            best = Some((rank, size));
+        }
+    }
+    if let Some((rank, _)) = best {
+        println!("  best: --rank={rank}");
+    }
+    Ok(())
+}
 
-    let mut bin_out = io::BufWriter::new(io::stdout());
-    let mut node = &root;
-    for bit in bytes_to_bits(input) {
-        loop {
-            match *node {
-                BTree::Tip(byte)  => { out.push(*byte); node = &root },
-                BTree::Bin(t1,t2) => { node = if !bit { t1 } else { t2 }; break },
+fn process(opts: &Options, name: &str, trainer: &mut Trainer) -> Result<()> {
+    match opts.mode {
+        Mode::Compress => {
+            let suffix = if opts.bz2 { BZ2_SUFFIX } else { SUFFIX };
+            if name.ends_with(suffix) && !opts.stdout {
+                return Err(usage(format!("already has {suffix} suffix -- unchanged")));
+            }
+            let out_name = format!("{name}{suffix}");
+            convert(opts, name, Some(out_name), true, |input, output| {
+                if opts.bz2 {
+                    let mut encoder = Bz2Encoder::new(output).level(opts.level).threads(opts.threads);
+                    io::copy(input, &mut encoder)?;
+                    encoder.finish()?;
+                    return Ok(());
+                }
+                let mut encoder = encoder(opts, output, opts.rank)?;
+                io::copy(input, &mut encoder)?;
+                encoder.finish()?;
+                Ok(())
+            })
+        }
+        Mode::Decompress | Mode::Test => {
+            let out_name = [SUFFIX, BZ2_SUFFIX].iter().find_map(|suffix| name.strip_suffix(suffix)).filter(|base| !base.is_empty());
+            if out_name.is_none() && name != "-" && !opts.stdout && opts.mode != Mode::Test {
+                return Err(usage("unknown suffix -- ignored"));
             }
+            convert(opts, name, out_name.map(str::to_string), false, |input, output| {
+                let mut output = io::BufWriter::new(output);
+                // .bz2 input is recognised by its magic, whatever the file is called
+                if input.fill_buf()?.starts_with(b"BZh") {
+                    io::copy(&mut Bz2Decoder::new(input).memory_limit(opts.memory), &mut output)?;
+                    return Ok(output.flush()?);
+                }
+                let mut decoder = BhuffDecoder::new(input).threads(opts.threads).memory_limit(opts.memory);
+                if let Some(model) = &opts.model {
+                    decoder = decoder.model(model);
+                }
+                io::copy(&mut decoder, &mut output)?;
+                Ok(output.flush()?)
+            })
+        }
+        Mode::List => {
+            let mut input = open_input(name, true, opts)?;
+            let Summary { method, pipeline, block_size, blocks, uncompressed } = list(&mut input)?;
+            let ratio = 100.0 - percentage(input.count, uncompressed);
+            let out_name = name.strip_suffix(SUFFIX).unwrap_or(name);
+            let stages = pipeline.map_or("-".to_string(), |pipeline| pipeline.to_string());
+            println!("{:>12} {:>12} {ratio:5.1}% {blocks:>7} {:>7} {method:<8} {stages:<12} {out_name}", input.count, uncompressed, block_size);
+            Ok(())
+        }
+        Mode::Train => trainer.add(open_input(name, false, opts)?),
+        Mode::Analyze => analyze(opts, name),
+        Mode::Stage => run_stage(opts.stage, open_input(name, false, opts)?, io::stdout().lock(), opts.level),
+    }
+}
+
+fn main() -> ExitCode {
+    let fail = |name: Option<&str>, err: BhuffError| {
+        match name {
+            Some(name) if name != "-" => eprintln!("bhuff: {name}: {err}"),
+            _ => eprintln!("bhuff: {err}"),
         }
+        ExitCode::from(err.exit_code())
     };
 
-    out.truncate(inp_len);
-    bin_out.write_all(&untransform(bw_pos, out.iter().cloned())).ok()?;
-    Some(())
-}
+    let (mut opts, mut files) = match parse_args(env::args().skip(1)) {
+        Ok(parsed) => parsed,
+        Err(err) => return fail(None, err),
+    };
+    if let Some(model_file) = &opts.model_file {
+        match fs::File::open(model_file).map_err(BhuffError::from).and_then(|file| Model::read(io::BufReader::new(file))) {
+            Ok(model) => opts.model = Some(model),
+            Err(err) => return fail(Some(model_file), err),
+        }
+    }
+    if files.is_empty() {
+        files.push("-".to_string());
+    }
+    if opts.mode == Mode::List {
+        println!("{:>12} {:>12} {:>6} {:>7} {:>7} {:<8} {:<12} name", "compressed", "uncompressed", "ratio", "blocks", "block", "method", "pipeline");
+    }
+
+    let mut status = ExitCode::SUCCESS;
+    let mut failed = false;
+    if opts.mode == Mode::Train {
+        let mut inputs = Vec::new();
+        for name in &files {
+            if let Err(err) = expand(name, &mut inputs) {
+                (status, failed) = (fail(Some(name), err), true);
+            }
+        }
+        files = inputs;
+    }
 
-static HUFFTREE : &BTree<u8> = { 
-    use BTree::{Tip,Bin}; 
-    include!("hufftree.in") 
-};
+    let mut trainer = Trainer::new().level(opts.level);
+    for name in &files {
+        if let Err(err) = process(&opts, name, &mut trainer) {
+            (status, failed) = (fail(Some(name), err), true);
+        }
+    }
 
-fn main() -> Result<(),Error> {
-    {
-        let args: Vec<String> = env::args().collect();
-        let input = io::BufReader::new(io::stdin()).bytes().map(|x|x.unwrap());
-        match args.get(1).map(|x|x.as_str()) {
-            Some("-train") => emit_hufftree(input),
-            Some("-d")     => decompress(HUFFTREE, input),
-            None           => compress(HUFFTREE, input),
-            _              => None,
+    // a model is only written if all of the training data could be read
+    if opts.mode == Mode::Train && !failed {
+        let model = trainer.model();
+        eprintln!("trained on {} files, {} bytes: {:.3} bits per byte", files.len(), trainer.bytes(), trainer.bits_per_byte(&model));
+        let written = match &opts.train_output {
+            Some(out_name) => open_output(Some(out_name), false, &opts).and_then(|output| model.write(output)),
+            None => model.write_tree(io::stdout()),
+        };
+        if let Err(err) = written {
+            status = fail(opts.train_output.as_deref(), err);
         }
     }
-    .ok_or_else(|| Error::other("a useless error message"))
+    status
 }
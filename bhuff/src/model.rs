@@ -0,0 +1,145 @@
+/* Huffman model files (.bht), to use a trained tree without rebuilding:
+ *
+ *   magic    4 bytes, "BHT\x1a"
+ *   version  1 byte
+ *   lengths  257 x 5 bits, canonical Huffman code length of every symbol,
+ *            padded with zero bits to a byte boundary
+ *
+ * The hash of a model is the CRC-32 of its lengths field; streams compressed
+ * with a model record it, so they are not decoded with a different one.
+ */
+
+use std::io::{Read,Write};
+use std::collections::HashMap;
+
+use crate::huffman::{BTree,BitString,LENGTH_BITS,bits_to_bytes,canonical_codes,canonical_tree,limited_code_lengths,read_code_lengths};
+use crate::plumbing::LocalPlumber;
+use crate::bitstring::BitReader;
+use crate::transform::ALPHABET;
+use crate::pipeline::Pipeline;
+use crate::block::{BLOCK_UNIT,read_input_block};
+use crate::error::{BhuffError,Result};
+use crate::crc;
+
+pub const MAGIC: [u8; 4] = *b"BHT\x1a";
+pub const VERSION: u8 = 1;
+
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct Model {
+    lengths: Vec<u8>,
+}
+
+impl Model {
+    // trains a model on the data read from input
+    pub fn train(input: impl Read) -> Result<Self> {
+        let mut trainer = Trainer::new();
+        trainer.add(input)?;
+        Ok(trainer.model())
+    }
+
+    pub fn read(mut input: impl Read) -> Result<Self> {
+        let mut head = [0; 5];
+        input.read_exact(&mut head).map_err(|err| match err.into() {
+            BhuffError::Truncated => BhuffError::BadTree,
+            err => err,
+        })?;
+        if head[..4] != MAGIC {
+            return Err(BhuffError::BadTree)
+        }
+        if head[4] != VERSION {
+            return Err(BhuffError::UnsupportedVersion(head[4]))
+        }
+        let mut packed = vec![0; (ALPHABET * LENGTH_BITS as usize).div_ceil(8)];
+        input.read_exact(&mut packed)?;
+        // a static tree must be able to encode every symbol
+        let lengths = read_code_lengths(&mut BitReader::new(&packed))
+            .filter(|lengths| lengths.iter().all(|&len| len > 0))
+            .ok_or(BhuffError::BadTree)?;
+        Ok(Model { lengths })
+    }
+
+    fn packed_lengths(&self) -> Vec<u8> {
+        let bits = self.lengths.iter().flat_map(|&len| BitString::with_len(len as usize, LENGTH_BITS));
+        bits_to_bytes(bits).collect()
+    }
+
+    pub fn write(&self, mut output: impl Write) -> Result<()> {
+        output.write_all(&MAGIC)?;
+        output.write_all(&[VERSION])?;
+        output.write_all(&self.packed_lengths())?;
+        Ok(output.flush()?)
+    }
+
+    // prints the tree as a Rust expression, for use as hufftree.in
+    pub fn write_tree(&self, mut output: impl Write) -> Result<()> {
+        let prealloc = &mut [BTree::Tip(0); 2*ALPHABET-2]; // 257 Tip + 256 Bin - 1 node in local variable
+        let tree = canonical_tree(&self.lengths, LocalPlumber(prealloc)).ok_or(BhuffError::BadTree)?;
+        Ok(writeln!(output, "{:?}", &tree)?)
+    }
+
+    pub fn hash(&self) -> u32 {
+        crc::crc32(&self.packed_lengths())
+    }
+
+    pub(crate) fn codes(&self) -> HashMap<u16, BitString> {
+        canonical_codes(&self.lengths)
+    }
+}
+
+/* Collects symbol frequencies from any number of inputs, one block at a time,
+ * so the training set does not have to fit in memory.
+ */
+pub struct Trainer {
+    block_size: usize,
+    counts: [u64; ALPHABET],
+    bytes: u64,
+}
+
+impl Default for Trainer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Trainer {
+    pub fn new() -> Self {
+        Trainer { block_size: 9 * BLOCK_UNIT, counts: [0; ALPHABET], bytes: 0 }
+    }
+
+    // the block size used for the transformation, as in BhuffEncoder::level
+    pub fn level(mut self, level: usize) -> Self {
+        self.block_size = level.clamp(1, 9) * BLOCK_UNIT;
+        self
+    }
+
+    pub fn add(&mut self, mut input: impl Read) -> Result<()> {
+        loop {
+            let data = read_input_block(&mut input, self.block_size)?;
+            if data.is_empty() {
+                break Ok(());
+            }
+            self.bytes += data.len() as u64;
+            let (_, block) = Pipeline::default().transform(data);
+            for sym in block {
+                self.counts[sym as usize] += 1;
+            }
+        }
+    }
+
+    // number of bytes trained on
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    pub fn model(&self) -> Model {
+        // every symbol needs a code, even if it does not occur in the training data
+        let ftab = (0..).zip(self.counts).map(|(sym, n)| (sym, n as usize + 1)).collect();
+        Model { lengths: limited_code_lengths::<ALPHABET>(&ftab).to_vec() }
+    }
+
+    // the average code length of the training data with the given model
+    pub fn bits_per_byte(&self, model: &Model) -> f64 {
+        let bits: u64 = self.counts.iter().zip(&model.lengths).map(|(&n, &len)| n * len as u64).sum();
+        bits as f64 / self.bytes.max(1) as f64
+    }
+}
@@ -0,0 +1,314 @@
+/* The transforms that a block goes through before the zero-run coding and
+ * the entropy coder, as a pipeline of stages that each work on the whole
+ * block. The default is the BWT followed by move-to-front; the stages are
+ * recorded in the header (see header.rs), and any parameters they need for
+ * the inverse (the start position of the BWT) in every block.
+ *
+ *   let pipeline = Pipeline::parse("bwt,mtf,rle")?;
+ *   let enc = BhuffEncoder::new(file).pipeline(pipeline);
+ */
+
+use std::fmt;
+
+use crate::header;
+use crate::transform::{bw_transform,bijective_transform,zero_run_encode,zero_run_decode,BwReverse};
+use crate::rank::{rank,unrank,max_ranked};
+use crate::block::MAX_BLOCK_SIZE;
+use crate::decoder::{DECODE_COST,DEFAULT_MEMORY_LIMIT};
+use crate::error::{BhuffError,Result};
+
+// the data produced by an inverse transform, which may be computed as it is read
+pub type Output = Box<dyn Iterator<Item=u8> + Send>;
+
+/* A stage of the pipeline. Its parameters are stored as varints with the
+ * block, so the inverse can be given any values, and has to check them. */
+pub trait Transform: Sync {
+    // the longest output for an input of the given length
+    fn max_output(&self, length: usize) -> usize {
+        length
+    }
+
+    // the number of parameters every block has for this stage
+    fn params(&self) -> usize {
+        0
+    }
+
+    fn forward(&self, data: Vec<u8>, params: &mut Vec<usize>) -> Vec<u8>;
+
+    // fails on invalid input, or if the output would be longer than max_len
+    fn inverse(&self, data: Vec<u8>, params: &[usize], max_len: usize) -> Option<Output>;
+}
+
+struct Bwt;
+struct Bwts;
+struct Rank(u8);
+struct Rle;
+struct Delta;
+struct X86;
+
+impl Transform for Bwt {
+    fn params(&self) -> usize {
+        1
+    }
+
+    fn forward(&self, data: Vec<u8>, params: &mut Vec<usize>) -> Vec<u8> {
+        let (startpos, vec) = bw_transform(&data);
+        params.push(startpos);
+        vec
+    }
+
+    // the start position of non-empty data is 1..=length, which BwReverse checks
+    fn inverse(&self, data: Vec<u8>, params: &[usize], max_len: usize) -> Option<Output> {
+        if data.len() > max_len {
+            return None;
+        }
+        Some(Box::new(BwReverse::new(params[0], &data)?))
+    }
+}
+
+impl Transform for Bwts {
+    fn forward(&self, data: Vec<u8>, _: &mut Vec<usize>) -> Vec<u8> {
+        bijective_transform(&data)
+    }
+
+    fn inverse(&self, data: Vec<u8>, _: &[usize], max_len: usize) -> Option<Output> {
+        if data.len() > max_len {
+            return None;
+        }
+        Some(Box::new(BwReverse::bijective(&data)?))
+    }
+}
+
+impl Transform for Rank {
+    fn max_output(&self, length: usize) -> usize {
+        max_ranked(self.0, length)
+    }
+
+    fn forward(&self, mut data: Vec<u8>, _: &mut Vec<usize>) -> Vec<u8> {
+        rank(self.0, &mut data);
+        data
+    }
+
+    fn inverse(&self, mut data: Vec<u8>, _: &[usize], max_len: usize) -> Option<Output> {
+        unrank(self.0, &mut data, max_len)?;
+        (data.len() <= max_len).then_some(Box::new(data.into_iter()))
+    }
+}
+
+/* Run-length coding as bzip2 does it first: after 4 equal bytes comes the
+ * number of times the byte is repeated further (0..=251). */
+impl Transform for Rle {
+    fn max_output(&self, length: usize) -> usize {
+        length + length / 4
+    }
+
+    fn forward(&self, data: Vec<u8>, _: &mut Vec<usize>) -> Vec<u8> {
+        let mut out = Vec::with_capacity(data.len());
+        let mut i = 0;
+        while i < data.len() {
+            let run = data[i..].iter().take(255).take_while(|&&c| c == data[i]).count();
+            out.extend(std::iter::repeat_n(data[i], run.min(4)));
+            if run >= 4 {
+                out.push((run - 4) as u8);
+            }
+            i += run;
+        }
+        out
+    }
+
+    fn inverse(&self, data: Vec<u8>, _: &[usize], max_len: usize) -> Option<Output> {
+        let mut out = Vec::with_capacity(data.len());
+        let mut count = 0;
+        for (i, &byte) in data.iter().enumerate() {
+            if count == 4 {
+                out.extend(std::iter::repeat_n(data[i - 1], byte as usize));
+                count = 0;
+            } else {
+                count = if i > 0 && byte == data[i - 1] { count + 1 } else { 1 };
+                out.push(byte);
+            }
+            if out.len() > max_len {
+                return None;
+            }
+        }
+        Some(Box::new(out.into_iter()))
+    }
+}
+
+// differences of successive bytes, for tables of numbers and sampled signals
+impl Transform for Delta {
+    fn forward(&self, mut data: Vec<u8>, _: &mut Vec<usize>) -> Vec<u8> {
+        for i in (1..data.len()).rev() {
+            data[i] = data[i].wrapping_sub(data[i - 1]);
+        }
+        data
+    }
+
+    fn inverse(&self, mut data: Vec<u8>, _: &[usize], max_len: usize) -> Option<Output> {
+        for i in 1..data.len() {
+            data[i] = data[i].wrapping_add(data[i - 1]);
+        }
+        (data.len() <= max_len).then_some(Box::new(data.into_iter()))
+    }
+}
+
+/* x86 code: the relative targets of calls and jumps (E8 and E9 followed by
+ * 4 bytes) are made absolute, so that calls of the same function look the
+ * same. The 4 bytes are skipped, so both directions find the same opcodes. */
+fn x86(data: &mut [u8], forward: bool) {
+    let mut i = 0;
+    while i + 5 <= data.len() {
+        if data[i] == 0xE8 || data[i] == 0xE9 {
+            let target = u32::from_le_bytes(data[i+1..i+5].try_into().unwrap());
+            let pos = (i + 5) as u32;
+            let target = if forward { target.wrapping_add(pos) } else { target.wrapping_sub(pos) };
+            data[i+1..i+5].copy_from_slice(&target.to_le_bytes());
+            i += 5;
+        } else {
+            i += 1;
+        }
+    }
+}
+
+impl Transform for X86 {
+    fn forward(&self, mut data: Vec<u8>, _: &mut Vec<usize>) -> Vec<u8> {
+        x86(&mut data, true);
+        data
+    }
+
+    fn inverse(&self, mut data: Vec<u8>, _: &[usize], max_len: usize) -> Option<Output> {
+        x86(&mut data, false);
+        (data.len() <= max_len).then_some(Box::new(data.into_iter()))
+    }
+}
+
+static RANKS: [Rank; 8] = [Rank(0), Rank(1), Rank(2), Rank(3), Rank(4), Rank(5), Rank(6), Rank(7)];
+
+// the names of the stages, with their numbers in the header
+pub const STAGE_NAMES: [(&str, u8); 13] = [
+    ("bwt", header::STAGE_BWT), ("bwts", header::STAGE_BWTS),
+    ("mtf", header::STAGE_MTF + header::RANK_MTF), ("mtf1", header::STAGE_MTF + header::RANK_MTF1),
+    ("mtf2", header::STAGE_MTF + header::RANK_MTF2), ("sticky", header::STAGE_MTF + header::RANK_STICKY),
+    ("wfc", header::STAGE_MTF + header::RANK_WFC), ("ts", header::STAGE_MTF + header::RANK_TIMESTAMP),
+    ("if", header::STAGE_MTF + header::RANK_IF), ("dc", header::STAGE_MTF + header::RANK_DC),
+    ("rle", header::STAGE_RLE), ("delta", header::STAGE_DELTA), ("x86", header::STAGE_X86),
+];
+
+// the ids have been checked when the header was read
+fn stage(id: u8) -> &'static dyn Transform {
+    match id {
+        header::STAGE_BWT   => &Bwt,
+        header::STAGE_BWTS  => &Bwts,
+        header::STAGE_RLE   => &Rle,
+        header::STAGE_DELTA => &Delta,
+        header::STAGE_X86   => &X86,
+        _ => &RANKS[(id - header::STAGE_MTF) as usize],
+    }
+}
+
+// the stages of move-to-front and its alternatives in rank.rs
+pub(crate) fn is_rank(id: u8) -> bool {
+    (header::STAGE_MTF..=header::STAGE_LAST_RANK).contains(&id)
+}
+
+pub const MAX_STAGES: usize = 8;
+
+/* the stages in the order they are applied; kept in an array, so that the
+ * header can be copied */
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct Pipeline {
+    len: u8,
+    stages: [u8; MAX_STAGES],
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Pipeline::new(&[header::STAGE_BWT, header::STAGE_MTF])
+    }
+}
+
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<&str> = self.stages().iter()
+            .map(|&id| STAGE_NAMES.iter().find(|&&(_, known)| known == id).unwrap().0)
+            .collect();
+        write!(f, "{}", names.join(","))
+    }
+}
+
+impl Pipeline {
+    pub(crate) fn new(stages: &[u8]) -> Self {
+        assert!(stages.len() <= MAX_STAGES, "too many stages");
+        let mut pipeline = Pipeline { len: stages.len() as u8, stages: [0; MAX_STAGES] };
+        pipeline.stages[..stages.len()].copy_from_slice(stages);
+        pipeline
+    }
+
+    /* stage names separated by commas, like "bwt,mtf,rle"; fails for unknown
+     * names, too many stages, or stages whose output for a full block would
+     * take more memory to decode than the default limit */
+    pub fn parse(names: &str) -> Result<Self> {
+        let stages = names.split(',')
+            .map(|name| STAGE_NAMES.iter().find(|&&(known, _)| known == name).map(|&(_, id)| id)
+                .ok_or_else(|| BhuffError::Usage(format!("unknown stage {name}"))))
+            .collect::<Result<Vec<u8>>>()?;
+        if stages.len() > MAX_STAGES {
+            return Err(BhuffError::Usage(format!("more than {MAX_STAGES} stages")));
+        }
+        let pipeline = Pipeline::new(&stages);
+        let cost = DECODE_COST * pipeline.max_len(MAX_BLOCK_SIZE);
+        if cost > DEFAULT_MEMORY_LIMIT {
+            return Err(BhuffError::Usage(format!("decoding would need {} MiB, more than the default memory limit", cost.div_ceil(1 << 20))));
+        }
+        Ok(pipeline)
+    }
+
+    pub(crate) fn stages(&self) -> &[u8] {
+        &self.stages[..self.len as usize]
+    }
+
+    // whether there is a rank stage, which BhuffEncoder::ranking replaces
+    pub fn has_rank(&self) -> bool {
+        self.stages().iter().any(|&id| is_rank(id))
+    }
+
+    // replaces the stages of a kind, as -bwts and --rank do
+    pub(crate) fn replace(mut self, kind: impl Fn(u8) -> bool, id: u8) -> Self {
+        let len = self.len as usize;
+        self.stages[..len].iter_mut().filter(|stage| kind(**stage)).for_each(|stage| *stage = id);
+        self
+    }
+
+    pub(crate) fn params(&self) -> usize {
+        self.stages().iter().map(|&id| stage(id).params()).sum()
+    }
+
+    // the longest data that the stages can make of a block
+    pub(crate) fn max_len(&self, length: usize) -> usize {
+        self.stages().iter().fold(length, |len, &id| stage(id).max_output(len))
+    }
+
+    /* all stages followed by the zero-run coding; returns the parameters of
+     * the block */
+    pub(crate) fn transform(&self, data: Vec<u8>) -> (Vec<usize>, Vec<u16>) {
+        let mut params = Vec::new();
+        let data = self.stages().iter().fold(data, |data, &id| stage(id).forward(data, &mut params));
+        (params, zero_run_encode(&data))
+    }
+
+    // fails on invalid input, or if the output would be longer than length
+    pub(crate) fn untransform(&self, params: &[usize], input: impl Iterator<Item=u16>, length: usize) -> Option<Output> {
+        // the longest input of every stage, and where its parameters start
+        let mut max_lens = vec![length];
+        let mut starts = vec![0];
+        for &id in self.stages() {
+            max_lens.push(stage(id).max_output(*max_lens.last().unwrap()));
+            starts.push(starts.last().unwrap() + stage(id).params());
+        }
+        let mut data: Output = Box::new(zero_run_decode(input, *max_lens.last().unwrap())?.into_iter());
+        for (i, &id) in self.stages().iter().enumerate().rev() {
+            data = stage(id).inverse(data.collect(), &params[starts[i]..starts[i + 1]], max_lens[i])?;
+        }
+        Some(data)
+    }
+}
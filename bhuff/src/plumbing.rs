@@ -4,6 +4,7 @@ pub trait Alloc<'b,T> {
     fn obtain(&mut self, x: T) -> &'b T;
 }
 
+#[allow(dead_code)]
 pub struct LeakyPlumber { }
 
 impl<'b,T> Alloc<'b,T> for LeakyPlumber where T: Debug {
@@ -0,0 +1,166 @@
+/* Adaptive binary range coding, as an alternative to Huffman coding: every
+ * symbol is coded as 9 binary decisions down a bit tree, each with its own
+ * adaptive probability, so frequent symbols (such as the zero runs) can take
+ * less than one bit. With order 1, the tree depends on the previous symbol.
+ *
+ * The coder is the one of LZMA:
+ * https://www.7-zip.org/sdk.html
+ */
+
+use crate::transform::ALPHABET;
+
+const SYMBOL_BITS: u32 = 9;
+const PROB_BITS: u32 = 11;
+const PROB_INIT: u16 = 1 << (PROB_BITS - 1);
+const ADAPT_SHIFT: u32 = 5;
+const TOP: u32 = 1 << 24;
+const CONTEXTS: usize = 4;
+
+// a probability is at least 31/2048, so a decision costs at most about 6 bits
+pub fn max_payload(symbols: usize) -> usize {
+    symbols * 7 + 5
+}
+
+struct Model {
+    order: usize,
+    probs: Vec<[u16; 1 << SYMBOL_BITS]>,
+    context: usize,
+}
+
+impl Model {
+    fn new(order: usize) -> Self {
+        let contexts = if order == 0 { 1 } else { CONTEXTS };
+        Model { order, probs: vec![[PROB_INIT; 1 << SYMBOL_BITS]; contexts], context: 0 }
+    }
+
+    // the previous symbol: RUNA, RUNB, MTF rank 1 (symbol 2), or anything larger
+    fn update(&mut self, sym: u16) {
+        if self.order > 0 {
+            self.context = (sym as usize).min(CONTEXTS - 1);
+        }
+    }
+}
+
+struct Encoder {
+    low: u64,
+    range: u32,
+    cache: u8,
+    cache_size: u64,
+    out: Vec<u8>,
+}
+
+impl Encoder {
+    fn new() -> Self {
+        Encoder { low: 0, range: u32::MAX, cache: 0, cache_size: 1, out: Vec::new() }
+    }
+
+    fn shift_low(&mut self) {
+        if (self.low as u32) < 0xFF00_0000 || self.low >> 32 != 0 {
+            let carry = (self.low >> 32) as u8;
+            let mut byte = self.cache;
+            while self.cache_size > 0 {
+                self.out.push(byte.wrapping_add(carry));
+                byte = 0xFF;
+                self.cache_size -= 1;
+            }
+            self.cache = (self.low >> 24) as u8;
+        }
+        self.cache_size += 1;
+        self.low = (self.low & 0x00FF_FFFF) << 8;
+    }
+
+    fn encode_bit(&mut self, prob: &mut u16, bit: bool) {
+        let bound = (self.range >> PROB_BITS) * *prob as u32;
+        if !bit {
+            self.range = bound;
+            *prob += ((1 << PROB_BITS) - *prob) >> ADAPT_SHIFT;
+        } else {
+            self.low += bound as u64;
+            self.range -= bound;
+            *prob -= *prob >> ADAPT_SHIFT;
+        }
+        while self.range < TOP {
+            self.range <<= 8;
+            self.shift_low();
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        for _ in 0..5 {
+            self.shift_low();
+        }
+        self.out
+    }
+}
+
+struct Decoder<'a> {
+    input: std::slice::Iter<'a, u8>,
+    range: u32,
+    code: u32,
+}
+
+impl<'a> Decoder<'a> {
+    // reading past the end gives zeroes; the checks on the block catch that
+    fn new(data: &'a [u8]) -> Self {
+        let mut dec = Decoder { input: data.iter(), range: u32::MAX, code: 0 };
+        for _ in 0..5 {
+            dec.code = dec.code << 8 | dec.next_byte();
+        }
+        dec
+    }
+
+    fn next_byte(&mut self) -> u32 {
+        self.input.next().map_or(0, |&byte| byte as u32)
+    }
+
+    fn decode_bit(&mut self, prob: &mut u16) -> bool {
+        let bound = (self.range >> PROB_BITS) * *prob as u32;
+        let bit = if self.code < bound {
+            self.range = bound;
+            *prob += ((1 << PROB_BITS) - *prob) >> ADAPT_SHIFT;
+            false
+        } else {
+            self.code -= bound;
+            self.range -= bound;
+            *prob -= *prob >> ADAPT_SHIFT;
+            true
+        };
+        while self.range < TOP {
+            self.range <<= 8;
+            self.code = self.code << 8 | self.next_byte();
+        }
+        bit
+    }
+}
+
+pub fn encode(order: usize, block: &[u16]) -> Vec<u8> {
+    let mut model = Model::new(order);
+    let mut enc = Encoder::new();
+    for &sym in block {
+        let probs = &mut model.probs[model.context];
+        let mut node = 1;
+        for i in (0..SYMBOL_BITS).rev() {
+            let bit = sym >> i & 1 != 0;
+            enc.encode_bit(&mut probs[node], bit);
+            node = node << 1 | bit as usize;
+        }
+        model.update(sym);
+    }
+    enc.finish()
+}
+
+pub fn decode(order: usize, data: &[u8], symbols: usize) -> Option<Vec<u16>> {
+    let mut model = Model::new(order);
+    let mut dec = Decoder::new(data);
+    (0..symbols).map(|_| {
+        let probs = &mut model.probs[model.context];
+        let mut node = 1;
+        for _ in 0..SYMBOL_BITS {
+            node = node << 1 | dec.decode_bit(&mut probs[node]) as usize;
+        }
+        let sym = (node - (1 << SYMBOL_BITS)) as u16;
+        model.update(sym);
+        // the bit tree has room for more symbols than there are
+        ((sym as usize) < ALPHABET).then_some(sym)
+    }).collect()
+}
@@ -0,0 +1,427 @@
+/* Alternatives to move-to-front as the second stage, which turns the local
+ * similarity of the BWT output into small numbers.
+ *
+ * The first ones are variants of the MTF list update rule; the list is the
+ * same for the encoder and the decoder, so they share the update.
+ * B. Balkenhol, S. Kurtz, Y.M. Shtarkov: "Modifications of the Burrows and
+ * Wheeler data compression algorithm" (MTF-1, MTF-2)
+ * https://doi.org/10.1109/DCC.1999.755668
+ * S. Deorowicz: "Improvements to Burrows-Wheeler compression algorithm"
+ * (weighted frequency count)
+ * https://doi.org/10.1002/1097-024X(20001110)30:13<1465::AID-SPE345>3.0.CO;2-D
+ * S. Albers: "Improved randomized on-line algorithms for the list update
+ * problem" (timestamp)
+ * https://doi.org/10.1137/S0097539794277858
+ *
+ * The last two code the positions of every byte value instead, and need its
+ * count or first position up front; their numbers can exceed 255.
+ * Z. Arnavut, S.S. Magliveras: "Block sorting and compression" (inversion
+ * frequencies)
+ * https://doi.org/10.1109/DCC.1997.582009
+ * E. Binder: "Distance coding" (posted to comp.compression, 2000)
+ */
+
+use crate::header::{self,put_varint};
+use crate::transform::{move_to_front,unmove_to_front};
+
+// the names of the rankings, with their numbers in the header
+pub const RANKINGS: [(&str, u8); 8] = [
+    ("mtf", header::RANK_MTF), ("mtf1", header::RANK_MTF1), ("mtf2", header::RANK_MTF2),
+    ("sticky", header::RANK_STICKY), ("wfc", header::RANK_WFC), ("ts", header::RANK_TIMESTAMP),
+    ("if", header::RANK_IF), ("dc", header::RANK_DC),
+];
+
+/* IF and DC put 256 numbers up to the length, then one number per byte. Those
+ * count the positions of larger (or still unknown) values they pass, and every
+ * position is passed at most once for each other value, so they add up to at
+ * most 255 times the length. A number n takes one byte and at most 2n/383 more,
+ * 383 being the smallest that takes three. The output of the other rankings
+ * has the length of their input. */
+pub fn max_ranked(rank: u8, length: usize) -> usize {
+    match rank {
+        header::RANK_IF | header::RANK_DC => 256 * number_len(length) + length + (510 * length).div_ceil(383),
+        _ => length,
+    }
+}
+
+pub fn rank(rank: u8, data: &mut Vec<u8>) {
+    match rank {
+        header::RANK_MTF => move_to_front(data),
+        header::RANK_IF  => *data = inversion_frequencies(data),
+        header::RANK_DC  => *data = distance_code(data),
+        _ => {
+            let mut list = List::new(rank);
+            for byte in data.iter_mut() {
+                let index = list.order.iter().position(|&c| c == *byte).unwrap();
+                list.update(index);
+                *byte = index as u8;
+            }
+        }
+    }
+}
+
+// fails on invalid input, or if the output of the last two would be longer than max_len
+pub fn unrank(rank: u8, data: &mut Vec<u8>, max_len: usize) -> Option<()> {
+    match rank {
+        header::RANK_MTF => unmove_to_front(data),
+        header::RANK_IF  => *data = undo_inversion_frequencies(data, max_len)?,
+        header::RANK_DC  => *data = undo_distance_code(data, max_len)?,
+        _ => {
+            let mut list = List::new(rank);
+            for index in data.iter_mut() {
+                let c = list.order[*index as usize];
+                list.update(*index as usize);
+                *index = c;
+            }
+        }
+    }
+    Some(())
+}
+
+/* the list of byte values, with what the update rules need to remember */
+struct List {
+    rank: u8,
+    order: [u8; 256],
+    last_index: usize,
+    // the number of bytes so far
+    time: u64,
+    // weighted frequency count: the weights of the occurrences in the window
+    score: [u32; 256],
+    window: [u8; WFC_WINDOW],
+    // timestamp: the times of the last two occurrences of every byte (0 = none)
+    last: [u64; 256],
+    before: [u64; 256],
+}
+
+/* the weight of an occurrence by how many bytes back it is, in steps up to each
+ * distance: 1 for the last byte, then about 1/(4 * distance), as in Deorowicz */
+const WFC_WEIGHTS: [(usize, u32); 12] = [
+    (1, 1 << 16), (2, 1 << 13), (4, 1 << 12), (8, 1 << 11), (16, 1 << 10), (32, 1 << 9),
+    (64, 1 << 8), (128, 1 << 7), (256, 1 << 6), (512, 1 << 5), (1024, 1 << 4), (2048, 1 << 3),
+];
+const WFC_WINDOW: usize = WFC_WEIGHTS[WFC_WEIGHTS.len() - 1].0;
+
+impl List {
+    fn new(rank: u8) -> Self {
+        List {
+            rank, order: std::array::from_fn(|i| i as u8), last_index: 0,
+            time: 0, score: [0; 256], window: [0; WFC_WINDOW],
+            last: [0; 256], before: [0; 256],
+        }
+    }
+
+    // moves the byte at index to where the rule puts it
+    fn update(&mut self, mut index: usize) {
+        let c = self.order[index];
+        let target = match self.rank {
+            // MTF-1 moves the second byte to the front, others only to the second place
+            header::RANK_MTF1 => match index {
+                0 | 1 => 0,
+                _ => 1,
+            },
+            // sticky MTF moves a byte only halfway, so one occurrence displaces little
+            header::RANK_STICKY => index / 2,
+            header::RANK_WFC => {
+                index = self.age(c);
+                let score = &self.score;
+                self.order[..index].partition_point(|&d| score[d as usize] > score[c as usize])
+            }
+            // in front of the first byte that occurred at most once since the last c
+            header::RANK_TIMESTAMP => {
+                self.time += 1;
+                let since = self.last[c as usize];
+                let target = self.order[..index].iter().position(|&d| self.before[d as usize] <= since).unwrap_or(index);
+                (self.before[c as usize], self.last[c as usize]) = (since, self.time);
+                target
+            }
+            // MTF-2 is MTF-1, except that a run of the front byte keeps its place
+            _ => match index {
+                0 => 0,
+                1 => (self.last_index == 0) as usize,
+                _ => 1,
+            },
+        };
+        self.order.copy_within(target..index, target + 1);
+        self.order[target] = c;
+        self.last_index = index;
+    }
+
+    /* Moves the occurrences that reach the end of a step on to the next one,
+     * and every byte that loses weight back behind those with at least as
+     * much; then adds the weight of c, and returns where it is now. */
+    fn age(&mut self, c: u8) -> usize {
+        let next_weights = WFC_WEIGHTS.iter().skip(1).map(|&(_, weight)| weight).chain([0]);
+        for (&(distance, weight), next) in WFC_WEIGHTS.iter().zip(next_weights) {
+            if let Some(pos) = self.time.checked_sub(distance as u64) {
+                let d = self.window[pos as usize % WFC_WINDOW];
+                self.score[d as usize] -= weight - next;
+                let from = self.order.iter().position(|&e| e == d).unwrap();
+                let score = &self.score;
+                let to = from + self.order[from + 1..].partition_point(|&e| score[e as usize] >= score[d as usize]);
+                self.order.copy_within(from + 1..=to, from);
+                self.order[to] = d;
+            }
+        }
+        self.window[self.time as usize % WFC_WINDOW] = c;
+        self.time += 1;
+        self.score[c as usize] += WFC_WEIGHTS[0].1;
+        self.order.iter().position(|&e| e == c).unwrap()
+    }
+}
+
+/* counts of the positions that are still set, with a binary indexed tree
+ * https://en.wikipedia.org/wiki/Fenwick_tree */
+struct Positions {
+    tree: Vec<u32>,
+}
+
+impl Positions {
+    fn new(n: usize) -> Self {
+        let mut tree = vec![1; n + 1];
+        tree[0] = 0;
+        for i in 1..=n {
+            let parent = i + (i & i.wrapping_neg());
+            if parent <= n {
+                tree[parent] += tree[i];
+            }
+        }
+        Positions { tree }
+    }
+
+    fn clear(&mut self, pos: usize) {
+        let mut i = pos + 1;
+        while i < self.tree.len() {
+            self.tree[i] -= 1;
+            i += i & i.wrapping_neg();
+        }
+    }
+
+    // the number of set positions before pos
+    fn count(&self, pos: usize) -> usize {
+        let (mut i, mut sum) = (pos, 0);
+        while i > 0 {
+            sum += self.tree[i] as usize;
+            i &= i - 1;
+        }
+        sum
+    }
+
+    // the position of the set position with the given count, if there is one
+    fn find(&self, mut count: usize) -> Option<usize> {
+        let mut pos = 0;
+        let mut step = self.tree.len().next_power_of_two();
+        while step > 0 {
+            if pos + step < self.tree.len() && (self.tree[pos + step] as usize) <= count {
+                pos += step;
+                count -= self.tree[pos] as usize;
+            }
+            step >>= 1;
+        }
+        (pos + 1 < self.tree.len()).then_some(pos)
+    }
+}
+
+// numbers up to 254 take one byte, larger ones an escape and a varint
+fn put_number(out: &mut Vec<u8>, n: usize) {
+    if n < 255 {
+        out.push(n as u8);
+    } else {
+        out.push(255);
+        put_varint(out, n - 255);
+    }
+}
+
+fn number_len(n: usize) -> usize {
+    match n.checked_sub(255) {
+        None => 1,
+        Some(n) => 2 + (usize::BITS - n.leading_zeros()).saturating_sub(1) as usize / 7,
+    }
+}
+
+fn get_number(input: &mut impl Iterator<Item=u8>) -> Option<usize> {
+    match input.next()? {
+        255 => {
+            let mut n: usize = 0;
+            for shift in (0..28).step_by(7) {
+                let byte = input.next()?;
+                n |= ((byte & 0x7F) as usize) << shift;
+                if byte & 0x80 == 0 {
+                    return Some(n + 255);
+                }
+            }
+            None
+        }
+        n => Some(n as usize),
+    }
+}
+
+fn positions_of(data: &[u8]) -> Vec<Vec<u32>> {
+    let mut positions = vec![Vec::new(); 256];
+    for (i, &c) in data.iter().enumerate() {
+        positions[c as usize].push(i as u32);
+    }
+    positions
+}
+
+/* Inversion frequencies: the counts of the byte values, then for every value
+ * in increasing order, the number of larger values before each occurrence
+ * (since the previous one). Only positions of values not yet coded are set. */
+fn inversion_frequencies(data: &[u8]) -> Vec<u8> {
+    let positions = positions_of(data);
+    let mut out = Vec::with_capacity(data.len() + 256);
+    for list in &positions {
+        put_number(&mut out, list.len());
+    }
+    let mut set = Positions::new(data.len());
+    for list in &positions {
+        let mut next = 0;
+        for &pos in list {
+            let count = set.count(pos as usize);
+            put_number(&mut out, count - next);
+            next = count + 1;
+        }
+        for &pos in list {
+            set.clear(pos as usize);
+        }
+    }
+    out
+}
+
+// the counts give the length
+fn undo_inversion_frequencies(data: &[u8], max_len: usize) -> Option<Vec<u8>> {
+    let mut input = data.iter().cloned();
+    let counts = (0..256).map(|_| get_number(&mut input)).collect::<Option<Vec<usize>>>()?;
+    let n = counts.iter().try_fold(0usize, |sum, &count| sum.checked_add(count)).filter(|&n| n <= max_len)?;
+    let mut out = vec![0; n];
+    let mut set = Positions::new(n);
+    for (c, &count) in counts.iter().enumerate() {
+        // the same positions are still set while a value is placed
+        let mut placed = Vec::with_capacity(count);
+        let mut next: usize = 0;
+        for _ in 0..count {
+            let pos = set.find(next.checked_add(get_number(&mut input)?)?)?;
+            out[pos] = c as u8;
+            placed.push(pos);
+            next = set.count(pos) + 1;
+        }
+        for pos in placed {
+            set.clear(pos);
+        }
+    }
+    input.next().is_none().then_some(out)
+}
+
+/* Distance coding: the first position of every byte value (plus one, or 0 if
+ * it does not occur), then for every position, the distance to the next
+ * occurrence of its value, counting only positions whose value is not known
+ * yet from an earlier distance; pointing past the end means there is none. */
+fn distance_code(data: &[u8]) -> Vec<u8> {
+    let positions = positions_of(data);
+    let mut out = Vec::with_capacity(data.len() + 256);
+    let mut unknown = Positions::new(data.len());
+    for list in &positions {
+        put_number(&mut out, list.first().map_or(0, |&pos| pos as usize + 1));
+        if let Some(&pos) = list.first() {
+            unknown.clear(pos as usize);
+        }
+    }
+    let mut next = [0; 256];
+    for (i, &c) in data.iter().enumerate() {
+        let list = &positions[c as usize];
+        next[c as usize] += 1;
+        let to = list.get(next[c as usize]).map_or(data.len(), |&pos| pos as usize);
+        put_number(&mut out, unknown.count(to) - unknown.count(i + 1));
+        if to < data.len() {
+            unknown.clear(to);
+        }
+    }
+    out
+}
+
+// there is a distance for every position, which gives the length
+fn undo_distance_code(data: &[u8], max_len: usize) -> Option<Vec<u8>> {
+    let mut input = data.iter().cloned();
+    let firsts = (0..256).map(|_| get_number(&mut input)).collect::<Option<Vec<usize>>>()?;
+    let distances = std::iter::from_fn(|| (input.len() > 0).then(|| get_number(&mut input))).collect::<Option<Vec<usize>>>()?;
+    let n = distances.len();
+    if n > max_len {
+        return None;
+    }
+    let mut out: Vec<Option<u8>> = vec![None; n];
+    let mut unknown = Positions::new(n);
+    for (c, &first) in firsts.iter().enumerate().filter(|&(_, &first)| first > 0) {
+        let slot = out.get_mut(first - 1)?;
+        if slot.is_some() {
+            return None;
+        }
+        *slot = Some(c as u8);
+        unknown.clear(first - 1);
+    }
+    // every value is known by the time it is reached
+    for (i, distance) in distances.into_iter().enumerate() {
+        let c = out[i]?;
+        let count = unknown.count(i + 1).checked_add(distance)?;
+        if let Some(pos) = unknown.find(count) {
+            out[pos] = Some(c);
+            unknown.clear(pos);
+        } else if count > unknown.count(n) {
+            return None;
+        }
+    }
+    out.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every value c is put after every 383 larger ones, so all the IF numbers take three bytes
+    fn worst_inversions(len: usize) -> Vec<u8> {
+        let mut data = vec![255; len];
+        for c in (0..255).rev() {
+            data = data.chunks(383).flat_map(|chunk| chunk.iter().cloned().chain([c])).collect();
+        }
+        data
+    }
+
+    fn random_bytes(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len).map(|_| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 56) as u8
+        }).collect()
+    }
+
+    fn ranked_len(rank: u8, data: &[u8]) -> usize {
+        let mut ranked = data.to_vec();
+        super::rank(rank, &mut ranked);
+        let len = ranked.len();
+        unrank(rank, &mut ranked, data.len()).unwrap();
+        assert_eq!(ranked, data);
+        len
+    }
+
+    #[test]
+    fn numbers_within_bound() {
+        let worst = worst_inversions(460_000);
+        let len = ranked_len(header::RANK_IF, &worst);
+        assert!(len <= max_ranked(header::RANK_IF, worst.len()));
+        assert!(len > max_ranked(header::RANK_IF, worst.len()) * 4 / 5);
+        for data in [worst, random_bytes(900_000, 1), vec![0; 900_000]] {
+            for rank in [header::RANK_IF, header::RANK_DC] {
+                assert!(ranked_len(rank, &data) <= max_ranked(rank, data.len()));
+            }
+        }
+    }
+
+    #[test]
+    fn weighted_frequency_count_is_not_mtf2() {
+        let text = include_bytes!("rank.rs");
+        let (mut wfc, mut mtf2) = (text.to_vec(), text.to_vec());
+        rank(header::RANK_WFC, &mut wfc);
+        rank(header::RANK_MTF2, &mut mtf2);
+        assert!(wfc.iter().zip(&mtf2).filter(|(a, b)| a != b).count() > text.len() / 4);
+        unrank(header::RANK_WFC, &mut wfc, text.len()).unwrap();
+        assert_eq!(wfc, text);
+    }
+}
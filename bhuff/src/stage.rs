@@ -0,0 +1,124 @@
+/* The stages of the compressor as separate filters, to look at or replace
+ * one of them; `bhuff bwt < file | bhuff mtf | bhuff huff` gives the blocks
+ * of a .bhf stream with method 1, without their CRCs.
+ *
+ * bwt reads raw data, and unbwt writes it; between the stages, data is a
+ * sequence of blocks:
+ *
+ *   length   varint, size of the original data in this block
+ *   bw_pos   varint, start position of the Burrows-Wheeler transform
+ *   data     length bytes: the transformed block after bwt,
+ *            and the move-to-front coded one after mtf
+ *
+ * huff writes the mtf blocks in the layout of header.rs instead:
+ *
+ *   length   varint
+ *   bw_pos   varint
+ *   symbols  varint, number of zero-run coded symbols
+ *   size     varint, number of bytes that follow
+ *   lengths  257 x 5 bits, canonical Huffman code length of every symbol
+ *   ...      Huffman coded symbols, padded with zero bits to a byte boundary
+ *
+ * The input ends after the last block; every block is checked as in a .bhf
+ * stream, so invalid input gives an error rather than a panic.
+ */
+
+use std::io::{BufRead,Write};
+
+use crate::huffman::{huffman_encode,read_code_lengths,canonical_codes};
+use crate::transform::{bw_transform,move_to_front,unmove_to_front,zero_run_encode,zero_run_decode,BwReverse};
+use crate::block::{BLOCK_UNIT,MAX_BLOCK_SIZE,read_input_block,read_payload};
+use crate::bitstring::BitReader;
+use crate::lookup::LookupTable;
+use crate::header::{put_varint,get_varint};
+use crate::error::{BhuffError,Result};
+use crate::tables;
+
+// the stages, each followed by its inverse
+pub const STAGES: [&str; 6] = ["bwt", "unbwt", "mtf", "unmtf", "huff", "unhuff"];
+
+fn write_block(output: &mut impl Write, bw_pos: usize, data: &[u8]) -> Result<()> {
+    let mut out = Vec::new();
+    put_varint(&mut out, data.len());
+    put_varint(&mut out, bw_pos);
+    output.write_all(&out)?;
+    output.write_all(data)?;
+    Ok(())
+}
+
+fn read_data(input: &mut impl BufRead, length: usize) -> Result<Vec<u8>> {
+    let mut data = vec![0; length];
+    input.read_exact(&mut data)?;
+    Ok(data)
+}
+
+// bwt makes blocks of 100k..900k as in BhuffEncoder::level; the other stages keep the blocks they read
+pub fn run_stage(stage: &str, mut input: impl BufRead, mut output: impl Write, level: usize) -> Result<()> {
+    if !STAGES.contains(&stage) {
+        return Err(BhuffError::Usage(format!("unknown stage {stage}")));
+    }
+    if !(1..=9).contains(&level) {
+        return Err(BhuffError::Usage(format!("compression level must be 1..=9, not {level}")));
+    }
+    if stage == "bwt" {
+        loop {
+            let data = read_input_block(&mut input, level * BLOCK_UNIT)?;
+            if data.is_empty() {
+                return Ok(output.flush()?);
+            }
+            let (bw_pos, block) = bw_transform(&data);
+            write_block(&mut output, bw_pos, &block)?;
+        }
+    }
+
+    let mut index = 0;
+    while !input.fill_buf()?.is_empty() {
+        index += 1;
+        let corrupt = |what| BhuffError::Corrupt(Some(index), what);
+        let length = get_varint(&mut input)?;
+        if length == 0 || length > MAX_BLOCK_SIZE {
+            return Err(corrupt("invalid block size"));
+        }
+        let bw_pos = get_varint(&mut input)?;
+        if bw_pos == 0 || bw_pos > length {
+            return Err(corrupt("start position out of range"));
+        }
+        match stage {
+            "unbwt" => {
+                let data = read_data(&mut input, length)?;
+                let original: Vec<u8> = BwReverse::new(bw_pos, &data).ok_or(corrupt("wrong length"))?.collect();
+                output.write_all(&original)?;
+            }
+            "mtf" | "unmtf" => {
+                let mut data = read_data(&mut input, length)?;
+                if stage == "mtf" { move_to_front(&mut data) } else { unmove_to_front(&mut data) }
+                write_block(&mut output, bw_pos, &data)?;
+            }
+            "huff" => {
+                let symbols = zero_run_encode(&read_data(&mut input, length)?);
+                let payload = huffman_encode(None, &symbols);
+                let mut out = Vec::new();
+                for n in [length, bw_pos, symbols.len(), payload.len()] {
+                    put_varint(&mut out, n);
+                }
+                output.write_all(&out)?;
+                output.write_all(&payload)?;
+            }
+            _ => {
+                let symbols = get_varint(&mut input)?;
+                if symbols == 0 || symbols > length {
+                    return Err(corrupt("too many symbols"));
+                }
+                let payload = read_payload(&mut input, index, tables::max_payload(symbols))?;
+                let mut bits = BitReader::new(&payload);
+                let table = LookupTable::new(&canonical_codes(&read_code_lengths(&mut bits).ok_or(BhuffError::BadTree)?));
+                let symbols = (0..symbols).map(|_| table.decode(&mut bits)).collect::<Option<Vec<u16>>>();
+                let data = zero_run_decode(symbols.ok_or(corrupt("invalid Huffman code"))?.into_iter(), length)
+                    .filter(|data| data.len() == length)
+                    .ok_or(corrupt("wrong length"))?;
+                write_block(&mut output, bw_pos, &data)?;
+            }
+        }
+    }
+    Ok(output.flush()?)
+}
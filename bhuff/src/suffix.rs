@@ -0,0 +1,404 @@
+/* Suffix array construction by induced sorting (SA-IS)
+ * G. Nong, S. Zhang, W.H. Chan: "Linear Suffix Array Construction by Almost Pure Induced-Sorting"
+ * https://doi.org/10.1109/DCC.2009.42
+ *
+ * The string is terminated by a virtual sentinel that is smaller than every
+ * symbol; i.e. a suffix that is a prefix of another suffix is the smaller one.
+ * The empty suffix itself is not part of the result.
+ *
+ * Indices are u32 and the reduced problem is stored in the unused part of the
+ * suffix array, so the working memory is about 4n bytes on top of the input.
+ */
+
+use std::ops::Range;
+
+const EMPTY: u32 = u32::MAX;
+
+pub trait Symbol: Copy + Eq {
+    fn idx(self) -> usize;
+}
+
+impl Symbol for u8 {
+    fn idx(self) -> usize { self as usize }
+}
+
+impl Symbol for u32 {
+    fn idx(self) -> usize { self as usize }
+}
+
+struct Types(Vec<u64>);
+
+impl Types {
+    // true = S-type, false = L-type
+    fn new<T: Symbol>(s: &[T]) -> Self {
+        let mut bits = Types(vec![0; s.len().div_ceil(64)]);
+        let mut stype = false;
+        for i in (0..s.len().saturating_sub(1)).rev() {
+            stype = s[i].idx() < s[i+1].idx() || (s[i] == s[i+1] && stype);
+            if stype {
+                bits.0[i / 64] |= 1 << (i % 64);
+            }
+        }
+        bits
+    }
+
+    fn is_s(&self, i: usize) -> bool {
+        self.0[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    fn is_lms(&self, i: usize) -> bool {
+        i > 0 && self.is_s(i) && !self.is_s(i-1)
+    }
+}
+
+fn bucket_heads<T: Symbol>(s: &[T], k: usize) -> Vec<u32> {
+    let mut bkt = vec![0; k];
+    for c in s {
+        bkt[c.idx()] += 1;
+    }
+    let mut sum = 0;
+    for b in bkt.iter_mut() {
+        (*b, sum) = (sum, sum + *b);
+    }
+    bkt
+}
+
+fn bucket_tails<T: Symbol>(s: &[T], k: usize) -> Vec<u32> {
+    let mut bkt = vec![0; k];
+    for c in s {
+        bkt[c.idx()] += 1;
+    }
+    let mut sum = 0;
+    for b in bkt.iter_mut() {
+        sum += *b;
+        *b = sum;
+    }
+    bkt
+}
+
+fn induce<T: Symbol>(s: &[T], sa: &mut [u32], types: &Types, k: usize) {
+    let n = s.len();
+
+    // the suffix in front of the sentinel is always L-type and comes first
+    let mut bkt = bucket_heads(s, k);
+    let c = s[n-1].idx();
+    sa[bkt[c] as usize] = (n-1) as u32;
+    bkt[c] += 1;
+    for i in 0..n {
+        let j = sa[i];
+        if j != EMPTY && j > 0 && !types.is_s(j as usize - 1) {
+            let c = s[j as usize - 1].idx();
+            sa[bkt[c] as usize] = j-1;
+            bkt[c] += 1;
+        }
+    }
+
+    let mut bkt = bucket_tails(s, k);
+    for i in (0..n).rev() {
+        let j = sa[i];
+        if j != EMPTY && j > 0 && types.is_s(j as usize - 1) {
+            let c = s[j as usize - 1].idx();
+            bkt[c] -= 1;
+            sa[bkt[c] as usize] = j-1;
+        }
+    }
+}
+
+fn lms_substrings_equal<T: Symbol>(s: &[T], types: &Types, a: usize, b: usize) -> bool {
+    let n = s.len();
+    for d in 0.. {
+        // only one LMS substring runs into the sentinel
+        if a+d == n || b+d == n {
+            return false
+        }
+        if s[a+d] != s[b+d] || types.is_s(a+d) != types.is_s(b+d) {
+            return false
+        }
+        if d > 0 && types.is_lms(a+d) {
+            return true
+        }
+    }
+    unreachable!()
+}
+
+fn sais<T: Symbol>(s: &[T], sa: &mut [u32], k: usize) {
+    let n = s.len();
+    match n {
+        0 => return,
+        1 => { sa[0] = 0; return },
+        _ => (),
+    }
+
+    let types = Types::new(s);
+
+    // step 1: sort the LMS substrings
+    sa.fill(EMPTY);
+    let mut bkt = bucket_tails(s, k);
+    for i in (1..n).rev().filter(|&i| types.is_lms(i)) {
+        let c = s[i].idx();
+        bkt[c] -= 1;
+        sa[bkt[c] as usize] = i as u32;
+    }
+    induce(s, sa, &types, k);
+
+    // step 2: name the LMS substrings
+    let mut n1 = 0;
+    for i in 0..n {
+        let j = sa[i] as usize;
+        if types.is_lms(j) {
+            sa[n1] = j as u32;
+            n1 += 1;
+        }
+    }
+    sa[n1..].fill(EMPTY);
+    let mut name = 0;
+    let mut prev = None;
+    for i in 0..n1 {
+        let pos = sa[i] as usize;
+        if prev.is_none_or(|prev| !lms_substrings_equal(s, &types, prev, pos)) {
+            name += 1;
+        }
+        prev = Some(pos);
+        // LMS positions are at least two apart, so this does not collide
+        sa[n1 + pos/2] = name - 1;
+    }
+    let mut j = n;
+    for i in (n1..n).rev() {
+        if sa[i] != EMPTY {
+            j -= 1;
+            sa[j] = sa[i];
+        }
+    }
+
+    // step 3: sort the LMS suffixes, recursively if the names are not unique
+    {
+        let (sa1, s1) = sa.split_at_mut(n - n1);
+        let (sa1, s1) = (&mut sa1[..n1], &mut s1[..]);
+        if (name as usize) < n1 {
+            sais(&*s1, sa1, name as usize);
+        } else {
+            for (i, &c) in s1.iter().enumerate() {
+                sa1[c as usize] = i as u32;
+            }
+        }
+
+        for (slot, i) in s1.iter_mut().zip((1..n).filter(|&i| types.is_lms(i))) {
+            *slot = i as u32;
+        }
+        for x in sa1.iter_mut() {
+            *x = s1[*x as usize];
+        }
+    }
+
+    // step 4: induce the order of all suffixes from the sorted LMS suffixes
+    sa[n1..].fill(EMPTY);
+    let mut bkt = bucket_tails(s, k);
+    for i in (0..n1).rev() {
+        let j = sa[i];
+        sa[i] = EMPTY;
+        let c = s[j as usize].idx();
+        bkt[c] -= 1;
+        sa[bkt[c] as usize] = j;
+    }
+    induce(s, sa, &types, k);
+}
+
+pub fn suffix_array(data: &[u8]) -> Vec<u32> {
+    assert!(data.len() < EMPTY as usize, "input too large for a 32-bit suffix array");
+    let mut sa = vec![0; data.len()];
+    sais(data, &mut sa, 256);
+    sa
+}
+
+/* Sorting the rotations of several strings by their infinite repetitions, for
+ * the bijective BWT. The strings are consecutive: ends has the end of every
+ * string, and each must be primitive (not a repetition of a shorter one).
+ * H. Bannai, J. Kärkkäinen, D. Köppl, M. Piątkowski: "Constructing the
+ * bijective and the extended Burrows-Wheeler transform in linear time"
+ * https://doi.org/10.4230/LIPIcs.CPM.2021.7
+ *
+ * This is SA-IS with the types taken around every string, so there is no
+ * sentinel; a string of length 1 has no type, and the repetition of its
+ * symbol sorts after the L-type rotations of its bucket and before the S-type
+ * ones. The LMS positions of a string, in order, form a string of names for
+ * the reduced problem, which is primitive again.
+ */
+
+// the positions of every string
+fn ranges(ends: &[u32]) -> impl Iterator<Item=Range<usize>> + '_ {
+    let starts = std::iter::once(0).chain(ends.iter().cloned());
+    starts.zip(ends).map(|(start, &end)| start as usize..end as usize)
+}
+
+struct Strings {
+    next: Vec<u32>,
+    prev: Vec<u32>,
+}
+
+impl Strings {
+    fn new(ends: &[u32]) -> Self {
+        let n = ends.last().map_or(0, |&end| end as usize);
+        let mut strings = Strings { next: vec![0; n], prev: vec![0; n] };
+        for range in ranges(ends) {
+            for i in range.clone() {
+                strings.next[i] = if i + 1 < range.end { i + 1 } else { range.start } as u32;
+                strings.prev[i] = if i > range.start { i - 1 } else { range.end - 1 } as u32;
+            }
+        }
+        strings
+    }
+
+    fn single(&self, i: usize) -> bool {
+        self.next[i] as usize == i
+    }
+}
+
+impl Types {
+    fn cyclic<T: Symbol>(s: &[T], ends: &[u32], strings: &Strings) -> Self {
+        let mut bits = Types(vec![0; s.len().div_ceil(64)]);
+        for range in ranges(ends) {
+            // the type of a symbol different from the next one is known, and the others follow back from it
+            let Some(mut i) = range.clone().find(|&i| s[i] != s[strings.next[i] as usize]) else {
+                continue;
+            };
+            let mut stype = false;
+            for _ in range {
+                let next = s[strings.next[i] as usize];
+                stype = s[i].idx() < next.idx() || (s[i] == next && stype);
+                if stype {
+                    bits.0[i / 64] |= 1 << (i % 64);
+                }
+                i = strings.prev[i] as usize;
+            }
+        }
+        bits
+    }
+
+    fn is_cyclic_lms(&self, strings: &Strings, i: usize) -> bool {
+        self.is_s(i) && !self.is_s(strings.prev[i] as usize)
+    }
+}
+
+fn induce_cyclic<T: Symbol>(s: &[T], sa: &mut [u32], types: &Types, strings: &Strings, k: usize) {
+    let n = s.len();
+
+    // the strings of length 1 go between the L- and the S-type rotations
+    let mut bkt = bucket_heads(s, k);
+    for i in (0..n).filter(|&i| !types.is_s(i) && !strings.single(i)) {
+        bkt[s[i].idx()] += 1;
+    }
+    for i in (0..n).filter(|&i| strings.single(i)) {
+        let c = s[i].idx();
+        sa[bkt[c] as usize] = i as u32;
+        bkt[c] += 1;
+    }
+
+    let mut bkt = bucket_heads(s, k);
+    for i in 0..n {
+        let j = sa[i];
+        if j != EMPTY {
+            let p = strings.prev[j as usize] as usize;
+            if p != j as usize && !types.is_s(p) {
+                let c = s[p].idx();
+                sa[bkt[c] as usize] = p as u32;
+                bkt[c] += 1;
+            }
+        }
+    }
+
+    let mut bkt = bucket_tails(s, k);
+    for i in (0..n).rev() {
+        let j = sa[i];
+        if j != EMPTY {
+            let p = strings.prev[j as usize] as usize;
+            if types.is_s(p) {
+                let c = s[p].idx();
+                bkt[c] -= 1;
+                sa[bkt[c] as usize] = p as u32;
+            }
+        }
+    }
+}
+
+// every string of length 2 or more has an LMS position, so this ends
+fn cyclic_lms_substrings_equal<T: Symbol>(s: &[T], types: &Types, strings: &Strings, mut a: usize, mut b: usize) -> bool {
+    for d in 0.. {
+        if s[a] != s[b] || types.is_s(a) != types.is_s(b) {
+            return false
+        }
+        if d > 0 && types.is_cyclic_lms(strings, a) {
+            return true
+        }
+        (a, b) = (strings.next[a] as usize, strings.next[b] as usize);
+    }
+    unreachable!()
+}
+
+fn sais_cyclic<T: Symbol>(s: &[T], ends: &[u32], sa: &mut [u32], k: usize) {
+    let n = s.len();
+    let strings = Strings::new(ends);
+    let types = Types::cyclic(s, ends, &strings);
+    let is_lms = |i: usize| types.is_cyclic_lms(&strings, i);
+
+    // step 1: sort the LMS substrings
+    sa.fill(EMPTY);
+    let mut bkt = bucket_tails(s, k);
+    for i in (0..n).filter(|&i| is_lms(i)) {
+        let c = s[i].idx();
+        bkt[c] -= 1;
+        sa[bkt[c] as usize] = i as u32;
+    }
+    induce_cyclic(s, sa, &types, &strings, k);
+
+    // step 2: name the LMS substrings
+    let sorted: Vec<u32> = sa.iter().cloned().filter(|&i| is_lms(i as usize)).collect();
+    let mut names = vec![EMPTY; n];
+    let mut name = 0;
+    let mut prev = None;
+    for &pos in &sorted {
+        if prev.is_none_or(|prev| !cyclic_lms_substrings_equal(s, &types, &strings, prev, pos as usize)) {
+            name += 1;
+        }
+        prev = Some(pos as usize);
+        names[pos as usize] = name - 1;
+    }
+
+    // step 3: sort the LMS rotations, recursively if the names are not unique
+    let lms: Vec<u32> = (0..n as u32).filter(|&i| is_lms(i as usize)).collect();
+    let mut order = vec![0; lms.len()];
+    if (name as usize) < lms.len() {
+        let s1: Vec<u32> = lms.iter().map(|&i| names[i as usize]).collect();
+        let mut ends1 = Vec::new();
+        let mut count = 0;
+        for range in ranges(ends) {
+            let start = count;
+            count += lms[count..].iter().take_while(|&&i| (i as usize) < range.end).count();
+            if count > start {
+                ends1.push(count as u32);
+            }
+        }
+        sais_cyclic(&s1, &ends1, &mut order, name as usize);
+        for x in order.iter_mut() {
+            *x = lms[*x as usize];
+        }
+    } else {
+        order = sorted;
+    }
+
+    // step 4: induce the order of all rotations from the sorted LMS rotations
+    sa.fill(EMPTY);
+    let mut bkt = bucket_tails(s, k);
+    for &i in order.iter().rev() {
+        let c = s[i as usize].idx();
+        bkt[c] -= 1;
+        sa[bkt[c] as usize] = i;
+    }
+    induce_cyclic(s, sa, &types, &strings, k);
+}
+
+pub fn rotation_array(data: &[u8], ends: &[u32]) -> Vec<u32> {
+    assert!(data.len() < EMPTY as usize, "input too large for a 32-bit suffix array");
+    let mut sa = vec![0; data.len()];
+    sais_cyclic(data, ends, &mut sa, 256);
+    sa
+}
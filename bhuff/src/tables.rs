@@ -0,0 +1,170 @@
+/* Multiple Huffman tables per block, as in bzip2: the symbols are coded in
+ * groups of GROUP_SIZE, and every group selects the table that codes it best.
+ *
+ * The tables are found iteratively: starting from tables that each favour a
+ * range of symbols, every group is assigned to its cheapest table, and the
+ * tables are rebuilt from the symbols of their groups.
+ *
+ *   tables     3 bits, the number of tables (1..=MAX_TABLES)
+ *   lengths    257 x 5 bits for every table
+ *   selectors  the table of every group, move-to-front coded, in unary
+ *              (truncated: the last table has no terminating zero bit)
+ *   ...        the Huffman coded symbols
+ */
+
+use crate::huffman::{BitString,MAX_CODE_LEN,LENGTH_BITS,limited_code_lengths,pad_lone_symbol,canonical_codes,read_code_lengths,bits_to_bytes,get_bits};
+use crate::bitstring::BitReader;
+use crate::lookup::LookupTable;
+use crate::transform::ALPHABET;
+
+pub const GROUP_SIZE: usize = 50;
+pub const MAX_TABLES: usize = 6;
+const TABLE_BITS: u8 = 3;
+const ITERATIONS: usize = 4;
+
+// the largest payload that a block of this many symbols can have
+pub fn max_payload(symbols: usize) -> usize {
+    let tables = TABLE_BITS as usize + MAX_TABLES * ALPHABET * LENGTH_BITS as usize;
+    (tables + symbols.div_ceil(GROUP_SIZE) * MAX_TABLES + symbols * MAX_CODE_LEN as usize).div_ceil(8)
+}
+
+/* The helpers below also serve bzip2.rs: the alphabet is that of freq and
+ * used, and N the size of the tables. */
+
+// symbols that are not used get no code; all others get one in every table
+fn table_lengths<const N: usize>(freq: &[usize], used: &[bool]) -> [u8; N] {
+    let mut ftab: Vec<(u16,usize)> = (0..).zip(freq).filter(|&(sym, _)| used[sym as usize]).map(|(sym, &n)| (sym, n.max(1))).collect();
+    pad_lone_symbol(&mut ftab, 1);
+    limited_code_lengths(&ftab)
+}
+
+// every table starts out cheap for a range of symbols with a similar total frequency
+fn initial_lengths<const N: usize>(freq: &[usize], ntables: usize) -> Vec<[u8; N]> {
+    let mut remaining: usize = freq.iter().sum();
+    let mut sym = 0;
+    (0..ntables).map(|t| {
+        let target = remaining / (ntables - t);
+        let mut lengths = [MAX_CODE_LEN; N];
+        let mut sum = 0;
+        while sym < freq.len() && (sum < target || t == ntables-1) {
+            lengths[sym] = 0;
+            sum += freq[sym];
+            sym += 1;
+        }
+        remaining -= sum;
+        lengths
+    }).collect()
+}
+
+fn group_cost(group: &[u16], lengths: &[u8]) -> usize {
+    group.iter().map(|&sym| lengths[sym as usize] as usize).sum()
+}
+
+fn select<const N: usize>(block: &[u16], tables: &[[u8; N]]) -> Vec<u8> {
+    block.chunks(GROUP_SIZE).map(|group| {
+        (0..tables.len()).min_by_key(|&t| group_cost(group, &tables[t])).unwrap() as u8
+    }).collect()
+}
+
+fn unary(index: u8, ntables: usize) -> BitString {
+    let len = (index + 1).min(ntables as u8 - 1);
+    BitString::with_len(((1 << index) - 1) << (len - index), len)
+}
+
+pub fn move_to_front(selectors: &[u8], ntables: usize) -> Vec<u8> {
+    let mut order: Vec<u8> = (0..ntables as u8).collect();
+    selectors.iter().map(|&t| {
+        let index = order.iter().position(|&x| x == t).unwrap();
+        order.remove(index);
+        order.insert(0, t);
+        index as u8
+    }).collect()
+}
+
+// finds the tables and the selectors for a number of tables, and the size they take up in bits
+pub fn optimize<const N: usize>(block: &[u16], freq: &[usize], used: &[bool], ntables: usize) -> (Vec<[u8; N]>, Vec<u8>, usize) {
+    let mut tables = initial_lengths(freq, ntables);
+    // a single table is simply that of the whole block
+    for _ in 0..if ntables == 1 { 1 } else { ITERATIONS } {
+        let selectors = select(block, &tables);
+        let mut group_freq = vec![[0; N]; ntables];
+        for (group, &t) in block.chunks(GROUP_SIZE).zip(&selectors) {
+            for &sym in group {
+                group_freq[t as usize][sym as usize] += 1;
+            }
+        }
+        tables = group_freq.iter().map(|freq| table_lengths(&freq[..used.len()], used)).collect();
+    }
+    let selectors = select(block, &tables);
+
+    let table_bits = ntables * N * LENGTH_BITS as usize;
+    let selector_bits: usize = move_to_front(&selectors, ntables).iter().map(|&j| unary(j, ntables).count()).sum();
+    let code_bits: usize = block.chunks(GROUP_SIZE).zip(&selectors).map(|(group, &t)| group_cost(group, &tables[t as usize])).sum();
+    (tables, selectors, table_bits + selector_bits + code_bits)
+}
+
+pub fn encode(block: &[u16]) -> Vec<u8> {
+    let mut freq = [0; ALPHABET];
+    for &sym in block {
+        freq[sym as usize] += 1;
+    }
+    let used = freq.map(|n| n > 0);
+
+    // more tables pay off for longer blocks; tables are added for as long as that helps
+    let mut best = optimize::<ALPHABET>(block, &freq, &used, 1);
+    for ntables in 2..=MAX_TABLES {
+        let next = optimize(block, &freq, &used, ntables);
+        if next.2 >= best.2 {
+            break;
+        }
+        best = next;
+    }
+    let (tables, selectors, _) = best;
+
+    let codes: Vec<_> = tables.iter().map(|lengths| canonical_codes(lengths)).collect();
+    let header = [BitString::with_len(tables.len(), TABLE_BITS)].into_iter()
+        .chain(tables.iter().flat_map(|lengths| lengths.map(|len| BitString::with_len(len as usize, LENGTH_BITS))))
+        .chain(move_to_front(&selectors, tables.len()).into_iter().map(|j| unary(j, tables.len())));
+    let symbols = block.chunks(GROUP_SIZE).zip(&selectors)
+        .flat_map(|(group, &t)| group.iter().map(|sym| codes[t as usize][sym]).collect::<Vec<_>>());
+    bits_to_bytes(header.chain(symbols).flatten()).collect()
+}
+
+pub fn read_tables(bits: &mut BitReader) -> Option<Vec<LookupTable>> {
+    let ntables = get_bits(bits, TABLE_BITS)? as usize;
+    if !(1..=MAX_TABLES).contains(&ntables) {
+        return None
+    }
+    (0..ntables).map(|_| read_code_lengths(bits).map(|lengths| LookupTable::new(&canonical_codes(&lengths)))).collect()
+}
+
+pub fn read_selectors(bits: &mut BitReader, ntables: usize, symbols: usize) -> Option<Vec<u8>> {
+    let mut order: Vec<u8> = (0..ntables as u8).collect();
+    (0..symbols.div_ceil(GROUP_SIZE)).map(|_| {
+        let mut index = 0;
+        while index < ntables-1 && bits.next()? {
+            index += 1;
+        }
+        let t = order.remove(index);
+        order.insert(0, t);
+        Some(t)
+    }).collect()
+}
+
+pub fn decode(bits: &mut BitReader, tables: &[LookupTable], selectors: &[u8], symbols: usize) -> Option<Vec<u16>> {
+    (0..symbols).map(|i| tables[selectors[i / GROUP_SIZE] as usize].decode(bits)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{compress_to_vec,decompress_to_vec};
+
+    // blocks whose symbols are all the top symbol 256
+    #[test]
+    fn lone_symbol() {
+        let descending: Vec<u8> = (0..=255).rev().collect();
+        for data in [vec![0xff], vec![0xff; 1000], vec![0; 1000], descending] {
+            assert_eq!(decompress_to_vec(&compress_to_vec(&data)).unwrap(), data);
+        }
+    }
+}
@@ -2,72 +2,376 @@
  * https://en.wikipedia.org/wiki/Burrows%E2%80%93Wheeler_transform
  */
 
-// these two functions can probably work in-place
-#[allow(clippy::ptr_arg)]
-fn bw_transform<T: Ord+Copy>(data: &Vec<T>) -> (usize, Vec<T>) {
-    let mut range: Vec<usize> = (0..=data.len()).collect();
-    range.sort_by_key(|&i| &data[i..]);
-    let startpos = range.iter().position(|&i| i==0).unwrap();
-    let vec = range.iter().filter_map(|&i| if i > 0 { Some(data[i-1]) } else { None } ).collect();
+use crate::suffix::{suffix_array,rotation_array};
+
+// the empty suffix (the virtual sentinel) sorts first, and its rotation ends in the last byte
+pub fn bw_transform(data: &[u8]) -> (usize, Vec<u8>) {
+    let range = suffix_array(data);
+    let startpos = range.iter().position(|&i| i==0).map_or(0, |pos| pos+1);
+    let vec = data.last().into_iter().cloned()
+        .chain(range.iter().filter_map(|&i| if i > 0 { Some(data[i as usize-1]) } else { None }))
+        .collect();
 
     (startpos, vec)
 }
 
-//this can be done as an iterator to get 'early output'
-fn bw_reverse<T: Ord+Copy>((startpos,data): &(usize,Vec<T>)) -> Vec<T> {
-    let mut range: Vec<(T,usize)> = data.iter().cloned().zip((0..=data.len()).filter(|&n|n!=*startpos)).collect();
-    let mut out = Vec::with_capacity(range.len());
-    range.sort_by_key(|&(c,_)| c);
-    let mut i = *startpos;
-    while i > 0 {
-        out.push(range[i-1].0);
-        i = range[i-1].1;
+/* bzip2 sorts the rotations instead: those of data are the suffixes of data+data
+ * cut to the length of data, and identical rotations end in the same byte */
+pub fn rotation_transform(data: &[u8]) -> (usize, Vec<u8>) {
+    let n = data.len();
+    let range = suffix_array(&[data, data].concat());
+    let rotations: Vec<usize> = range.into_iter().map(|i| i as usize).filter(|&i| i < n).collect();
+    let orig_ptr = rotations.iter().position(|&i| i == 0).unwrap_or(0);
+    (orig_ptr, rotations.iter().map(|&i| data[(i + n - 1) % n]).collect())
+}
+
+/* The bijective BWT sorts the rotations of the Lyndon factors of the data
+ * (by comparing their infinite repetitions), so it needs no start position.
+ * J.Y. Gil, D.A. Scott: "A bijective string sorting transform"
+ * https://arxiv.org/abs/1201.3077
+ *
+ * The factors are found with Duval's algorithm, and their rotations are
+ * sorted in linear time by induced sorting (see suffix.rs).
+ */
+pub fn bijective_transform(data: &[u8]) -> Vec<u8> {
+    let n = data.len();
+    let mut ends = Vec::new();
+    let mut i = 0;
+    while i < n {
+        let (mut j, mut k) = (i + 1, i);
+        while j < n && data[k] <= data[j] {
+            k = if data[k] < data[j] { i } else { k + 1 };
+            j += 1;
+        }
+        while i <= k {
+            i += j - k;
+            ends.push(i as u32);
+        }
+    }
+
+    // every rotation ends in the byte before it in its factor
+    let mut prev: Vec<usize> = (0..n).map(|i| i.wrapping_sub(1)).collect();
+    for (start, &end) in std::iter::once(0).chain(ends.iter().cloned()).zip(&ends) {
+        prev[start as usize] = end as usize - 1;
+    }
+    rotation_array(data, &ends).iter().map(|&p| data[prev[p as usize]]).collect()
+}
+
+/* The inverse transform follows the LF mapping: sorting the bytes of the BWT
+ * (stably, with a counting sort) gives the first column of the sorted
+ * rotations, and each entry records where the rotation continues. The byte
+ * is kept in the low 8 bits of its entry, so this needs one u32 per byte, and
+ * the data can be produced while following the links.
+ */
+pub struct BwReverse {
+    links: Vec<u32>,
+    pos: usize,
+    left: usize,
+    // the bijective transform: the factor being produced, and those after it
+    start: usize,
+    starts: Vec<u32>,
+}
+
+const MAX_LINKS: usize = 1 << 24;
+
+// the entries of the first column; next gives where the rotation of position i continues
+fn lf_links(data: &[u8], next: impl Fn(usize) -> usize) -> Vec<u32> {
+    let mut offset = [0; 256];
+    for &c in data {
+        offset[c as usize] += 1;
+    }
+    let mut sum = 0;
+    for n in offset.iter_mut() {
+        (*n, sum) = (sum, sum + *n);
+    }
+    let mut links = vec![0; data.len()];
+    for (i, &c) in data.iter().enumerate() {
+        links[offset[c as usize]] = (next(i) as u32) << 8 | c as u32;
+        offset[c as usize] += 1;
+    }
+    links
+}
+
+impl BwReverse {
+    pub fn new(startpos: usize, data: &[u8]) -> Option<Self> {
+        // the start position of non-empty data points at one of its rotations
+        if startpos > data.len() || (startpos == 0) != data.is_empty() || data.len() >= MAX_LINKS {
+            return None
+        }
+        // the rotation at startpos is the one that starts with the sentinel
+        let links = lf_links(data, |i| if i < startpos { i } else { i+1 });
+        Some(BwReverse { left: links.len(), links, pos: startpos, start: 0, starts: Vec::new() })
+    }
+
+    // the inverse of rotation_transform, which has no sentinel to end the cycle
+    pub fn rotations(orig_ptr: usize, data: &[u8]) -> Option<Self> {
+        if orig_ptr >= data.len() || data.len() >= MAX_LINKS {
+            return None
+        }
+        let links = lf_links(data, |i| i+1);
+        Some(BwReverse { left: links.len(), links, pos: orig_ptr + 1, start: 0, starts: Vec::new() })
+    }
+
+    /* Every cycle of the links is one Lyndon factor, starting with the first
+     * of its rotations that is found; the factors are found in increasing
+     * order, and are produced in decreasing order. */
+    pub fn bijective(data: &[u8]) -> Option<Self> {
+        if data.len() >= MAX_LINKS {
+            return None
+        }
+        let links = lf_links(data, |i| i+1);
+        let mut seen = vec![false; links.len()];
+        let mut starts = Vec::new();
+        for first in 0..links.len() {
+            if seen[first] {
+                continue;
+            }
+            starts.push(first as u32 + 1);
+            let mut pos = first;
+            while !seen[pos] {
+                seen[pos] = true;
+                pos = (links[pos] >> 8) as usize - 1;
+            }
+        }
+        let start = starts.pop().map_or(0, |start| start as usize);
+        Some(BwReverse { left: links.len(), links, pos: start, start, starts })
+    }
+}
+
+// at most one byte per link, so this always terminates
+impl Iterator for BwReverse {
+    type Item = u8;
+    fn next(&mut self) -> Option<u8> {
+        self.left = self.left.checked_sub(1)?;
+        let link = *self.links.get(self.pos.checked_sub(1)?)?;
+        self.pos = (link >> 8) as usize;
+        if self.pos == self.start && let Some(start) = self.starts.pop() {
+            (self.start, self.pos) = (start as usize, start as usize);
+        }
+        Some(link as u8)
     }
-    out
 }
 
 /* The obligatory 'move to front' transformation:
  * https://en.wikipedia.org/wiki/Move-to-front_transform
+ *
+ * After the BWT, most bytes are at the front of the list or next to it, so
+ * these are tried first; other bytes are searched for eight at a time.
  */
 
-#[allow(clippy::needless_range_loop)]
-fn move_to_front(data: &mut [u8]) {
-    let mut alphabet: [u8; 256] = [0; 256];
-    for i in 0..=255 {
-        alphabet[i] = i as u8
+const ONES: u64 = 0x0101_0101_0101_0101;
+const HIGHS: u64 = 0x8080_8080_8080_8080;
+
+// the position of a byte in the list, which holds every byte
+fn position(alphabet: &[u8; 256], byte: u8) -> usize {
+    let pattern = ONES * byte as u64;
+    for (i, chunk) in alphabet.chunks_exact(8).enumerate() {
+        // a byte of x is zero where the byte was found; the lowest flag is exact
+        let x = u64::from_le_bytes(chunk.try_into().unwrap()) ^ pattern;
+        let found = x.wrapping_sub(ONES) & !x & HIGHS;
+        if found != 0 {
+            return i * 8 + found.trailing_zeros() as usize / 8;
+        }
     }
+    unreachable!("every byte is in the list")
+}
+
+pub fn move_to_front(data: &mut [u8]) {
+    let mut alphabet: [u8; 256] = std::array::from_fn(|i| i as u8);
     for byte in data.iter_mut() {
-        let index = alphabet.iter().position(|&c|c==*byte).unwrap();
-        alphabet.copy_within(0..index, 1);
-        alphabet[0] = *byte;
-        *byte = index as u8;
+        let c = *byte;
+        *byte = if alphabet[0] == c {
+            0
+        } else if alphabet[1] == c {
+            alphabet.swap(0, 1);
+            1
+        } else {
+            let index = position(&alphabet, c);
+            alphabet.copy_within(0..index, 1);
+            alphabet[0] = c;
+            index as u8
+        };
     }
 }
 
-#[allow(clippy::needless_range_loop)]
-fn unmove_to_front(data: &mut [u8]) {
-    let mut alphabet: [u8; 256] = [0; 256];
-    for i in 0..=255 {
-        alphabet[i] = i as u8
-    }
+pub fn unmove_to_front(data: &mut [u8]) {
+    let mut alphabet: [u8; 256] = std::array::from_fn(|i| i as u8);
     for index in data.iter_mut() {
         let pos = *index as usize;
-        let c = alphabet[pos];
-        alphabet.copy_within(0..pos, 1);
-        alphabet[0] = c;
-        *index = c;
+        *index = match pos {
+            0 => alphabet[0],
+            1 => {
+                alphabet.swap(0, 1);
+                alphabet[0]
+            }
+            _ => {
+                let c = alphabet[pos];
+                alphabet.copy_within(0..pos, 1);
+                alphabet[0] = c;
+                c
+            }
+        };
     }
 }
 
-/* combined BW & MTF transformation */
-pub fn transform(input: impl Iterator<Item=u8>) -> (usize, Vec<u8>) {
-    let (startpos, mut vec) = bw_transform(&input.collect());
-    move_to_front(&mut vec);
-    (startpos, vec)
+/* Zero-run length coding, as in bzip2:
+ * after MTF, a run of n zeroes is written as n in bijective base 2, least
+ * significant digit first, using the digits RUNA (1) and RUNB (2); all other
+ * values are shifted up by one to make room for these two symbols.
+ */
+
+pub const RUNA: u16 = 0;
+pub const RUNB: u16 = 1;
+pub const ALPHABET: usize = 257;
+
+pub fn zero_run_encode(data: &[u8]) -> Vec<u16> {
+    fn flush(out: &mut Vec<u16>, mut run: usize) {
+        while run > 0 {
+            if run & 1 == 1 {
+                out.push(RUNA);
+                run = (run-1) / 2;
+            } else {
+                out.push(RUNB);
+                run = (run-2) / 2;
+            }
+        }
+    }
+
+    let mut out = Vec::with_capacity(data.len());
+    let mut run = 0;
+    for &byte in data {
+        if byte == 0 {
+            run += 1;
+        } else {
+            flush(&mut out, run);
+            run = 0;
+            out.push(byte as u16 + 1);
+        }
+    }
+    flush(&mut out, run);
+    out
 }
 
-pub fn untransform(startpos: usize, input: impl Iterator<Item=u8>) -> Vec<u8> {
-    let mut vec = input.collect::<Vec<_>>();
-    unmove_to_front(&mut vec);
-    bw_reverse(&(startpos, vec))
+// a run is at least as long as its weight, so checking it also prevents overflow
+pub fn zero_run_decode(input: impl Iterator<Item=u16>, max_len: usize) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut run = 0;
+    let mut weight = 1;
+    for sym in input {
+        match sym {
+            RUNA => { run += weight; weight <<= 1 },
+            RUNB => { run += 2*weight; weight <<= 1 },
+            _    => {
+                out.extend(std::iter::repeat_n(0, run));
+                (run, weight) = (0, 1);
+                out.push((sym - 1) as u8);
+            }
+        }
+        if out.len() + run > max_len {
+            return None
+        }
+    }
+    out.extend(std::iter::repeat_n(0, run));
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the plain move-to-front that the fast one replaced
+    fn reference_mtf(data: &mut [u8]) {
+        let mut alphabet: [u8; 256] = std::array::from_fn(|i| i as u8);
+        for byte in data.iter_mut() {
+            let index = alphabet.iter().position(|&c| c == *byte).unwrap();
+            alphabet.copy_within(0..index, 1);
+            alphabet[0] = *byte;
+            *byte = index as u8;
+        }
+    }
+
+    fn reference_unmtf(data: &mut [u8]) {
+        let mut alphabet: [u8; 256] = std::array::from_fn(|i| i as u8);
+        for index in data.iter_mut() {
+            let c = alphabet[*index as usize];
+            alphabet.copy_within(0..*index as usize, 1);
+            alphabet[0] = c;
+            *index = c;
+        }
+    }
+
+    fn random_bytes(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len).map(|_| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 56) as u8
+        }).collect()
+    }
+
+    fn assert_same_mtf(data: &[u8]) {
+        let (mut fast, mut slow) = (data.to_vec(), data.to_vec());
+        move_to_front(&mut fast);
+        reference_mtf(&mut slow);
+        assert_eq!(fast, slow);
+        // the data read as ranks
+        let (mut fast, mut slow) = (data.to_vec(), data.to_vec());
+        unmove_to_front(&mut fast);
+        reference_unmtf(&mut slow);
+        assert_eq!(fast, slow);
+    }
+
+    #[test]
+    fn move_to_front_matches_reference() {
+        assert_same_mtf(&random_bytes(100000, 1));
+        // few distinct bytes, mostly ranks 0 and 1
+        let text: Vec<u8> = random_bytes(100000, 2).iter().map(|&c| b"abc"[c as usize % 3]).collect();
+        assert_same_mtf(&text);
+        let source = include_bytes!("transform.rs");
+        assert_same_mtf(&bw_transform(source).1);
+        assert_same_mtf(&bw_transform(&text).1);
+        for rank in [0, 1, 255] {
+            assert_same_mtf(&[rank; 1000]);
+        }
+        let mixed: Vec<u8> = random_bytes(10000, 3).iter().map(|&c| [0, 1, 255][c as usize % 3]).collect();
+        assert_same_mtf(&mixed);
+    }
+
+    // smaller than all its proper rotations
+    fn is_lyndon(word: &[u8]) -> bool {
+        (1..word.len()).all(|i| word < &[&word[i..], &word[..i]].concat()[..])
+    }
+
+    // the longest Lyndon prefixes, and the rotations compared a length at which they must differ
+    fn reference_bijective(data: &[u8]) -> Vec<u8> {
+        let mut rotations = Vec::new();
+        let mut start = 0;
+        while start < data.len() {
+            let end = (start + 1..=data.len()).rev().find(|&end| is_lyndon(&data[start..end])).unwrap();
+            let factor = &data[start..end];
+            for i in 0..factor.len() {
+                let rotation = [&factor[i..], &factor[..i]].concat();
+                let key: Vec<u8> = rotation.iter().cycle().take(2 * data.len()).cloned().collect();
+                rotations.push((key, *rotation.last().unwrap()));
+            }
+            start = end;
+        }
+        rotations.sort();
+        rotations.into_iter().map(|(_, last)| last).collect()
+    }
+
+    #[test]
+    fn bijective_matches_reference() {
+        let mut inputs: Vec<Vec<u8>> = Vec::new();
+        for len in 0..=8 {
+            for mut x in 0..3usize.pow(len) {
+                inputs.push((0..len).map(|_| { let c = b"abc"[x % 3]; x /= 3; c }).collect());
+            }
+        }
+        inputs.extend((0..50).map(|seed| random_bytes(200, seed).iter().map(|&c| c % 4).collect()));
+        for data in inputs {
+            let bwts = bijective_transform(&data);
+            assert_eq!(bwts, reference_bijective(&data));
+            assert_eq!(BwReverse::bijective(&bwts).unwrap().collect::<Vec<u8>>(), data);
+        }
+    }
 }